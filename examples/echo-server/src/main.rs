@@ -0,0 +1,94 @@
+//! Websocket echo server for the examples and the integration tests.
+//!
+//! Every message is sent back as it was received, and close frames are answered with the code
+//! and reason the client sent, so tests can check what arrived at the server. Binary messages
+//! can also ask the server to end the connection:
+//!
+//! - `close <code> [reason]` closes it with that code and reason
+//! - `drop` drops it without a closing handshake
+//!
+//! Connecting to the `/drop` path drops the connection right after the handshake, connecting to
+//! `/greeting` makes the server send `hello` first.
+//!
+//! It runs natively:
+//!
+//! ```sh
+//! cargo run -p echo-server --target x86_64-unknown-linux-gnu [address]
+//! ```
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() -> std::io::Result<()> {
+    use std::net::TcpListener;
+
+    let addr = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:8000".to_string());
+    let listener = TcpListener::bind(&addr)?;
+    println!("listening on {}", addr);
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        std::thread::spawn(move || {
+            if let Err(e) = echo(stream) {
+                eprintln!("connection failed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn echo(stream: std::net::TcpStream) -> Result<(), Box<dyn std::error::Error>> {
+    use tungstenite::protocol::CloseFrame;
+    use tungstenite::{Error, Message};
+
+    use tungstenite::handshake::server::{Request, Response};
+
+    let mut path = String::new();
+    // the callback's error type is tungstenite's, an http response
+    #[allow(clippy::result_large_err)]
+    let mut ws = tungstenite::accept_hdr(stream, |request: &Request, response: Response| {
+        path = request.uri().path().to_string();
+        Ok(response)
+    })
+    // the handshake error holds on to the callback
+    .map_err(|e| e.to_string())?;
+    match path.as_str() {
+        "/drop" => return Ok(()),
+        "/greeting" => ws.send(Message::binary(&b"hello"[..]))?,
+        _ => {}
+    }
+
+    loop {
+        match ws.read() {
+            Ok(Message::Close(frame)) => {
+                match &frame {
+                    Some(frame) => {
+                        println!("closed with {} {:?}", frame.code, frame.reason.as_str())
+                    }
+                    None => println!("closed without a code"),
+                }
+                // tungstenite queued a reply with the same code and reason, this flushes it
+                let _ = ws.close(frame);
+            }
+            Ok(Message::Binary(data)) if &data[..] == b"drop" => return Ok(()),
+            Ok(Message::Binary(data)) if data.starts_with(b"close ") => {
+                let command = String::from_utf8_lossy(&data["close ".len()..]).into_owned();
+                let (code, reason) = command.split_once(' ').unwrap_or((&command, ""));
+                let frame = CloseFrame {
+                    code: code.parse::<u16>()?.into(),
+                    reason: reason.into(),
+                };
+                ws.close(Some(frame))?;
+            }
+            Ok(message @ (Message::Binary(_) | Message::Text(_))) => ws.send(message)?,
+            Ok(_) => {}
+            Err(Error::ConnectionClosed | Error::AlreadyClosed) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+// the workspace is built for the wasm target by default, where there's nothing to run
+#[cfg(target_arch = "wasm32")]
+fn main() {}
@@ -0,0 +1,197 @@
+use crate::WebsocketError;
+
+/// Builds the url for `addr` (`host[:port][/path]`), validating it up front so mistakes are
+/// reported with a description instead of a browser exception.
+pub(crate) fn websocket_url(addr: &str, secure: bool) -> Result<String, WebsocketError> {
+    let invalid = |reason: &str| WebsocketError::InvalidAddress {
+        addr: addr.to_string(),
+        reason: reason.to_string(),
+    };
+
+    if addr.contains("://") {
+        return Err(invalid("expected an address without a scheme"));
+    }
+    if let Some(c) = addr.chars().find(|c| c.is_whitespace() || c.is_control()) {
+        return Err(invalid(&format!("contains the invalid character {:?}", c)));
+    }
+
+    let authority_end = addr.find(['/', '?', '#']).unwrap_or(addr.len());
+    let (authority, rest) = addr.split_at(authority_end);
+    if authority.contains('@') {
+        return Err(invalid("credentials can't be passed in the address"));
+    }
+    if authority.contains('%') {
+        // the URL standard has no syntax for the zone of a link-local IPv6 address
+        return Err(invalid("IPv6 zone ids like `%eth0` aren't supported"));
+    }
+    let authority = bracket_ipv6(authority);
+    let authority = authority.as_str();
+
+    let port = match authority.strip_prefix('[') {
+        Some(bracketed) => {
+            let (host, after) = bracketed
+                .split_once(']')
+                .ok_or_else(|| invalid("missing the closing `]` of an IPv6 address"))?;
+            if host.parse::<std::net::Ipv6Addr>().is_err() {
+                return Err(invalid("invalid IPv6 address"));
+            }
+            match after {
+                "" => None,
+                _ => Some(
+                    after
+                        .strip_prefix(':')
+                        .ok_or_else(|| invalid("unexpected characters after the IPv6 address"))?,
+                ),
+            }
+        }
+        None => {
+            let (host, port) = match authority.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            };
+            if host.is_empty() {
+                return Err(invalid("missing a host"));
+            }
+            let valid = |c: char| c.is_alphanumeric() || c == '-' || c == '.' || c == '_';
+            if let Some(c) = host.chars().find(|&c| !valid(c)) {
+                return Err(invalid(&format!(
+                    "the host contains the invalid character {:?}",
+                    c
+                )));
+            }
+            port
+        }
+    };
+
+    if let Some(port) = port {
+        if port.parse::<u16>().is_err() {
+            return Err(invalid("the port must be a number between 0 and 65535"));
+        }
+    }
+
+    let scheme = if secure { "wss" } else { "ws" };
+    Ok(format!("{}://{}{}", scheme, authority, rest))
+}
+
+/// Adds the brackets urls require around IPv6 literals, so `::1:8080` becomes `[::1]:8080`.
+///
+/// A trailing group is taken as the port if the rest is a valid address by itself, so pass
+/// addresses ending in a group without a port in brackets.
+fn bracket_ipv6(authority: &str) -> String {
+    use std::net::Ipv6Addr;
+
+    if authority.starts_with('[') || authority.matches(':').count() < 2 {
+        return authority.to_string();
+    }
+    if let Some((host, port)) = authority.rsplit_once(':') {
+        let is_port = !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit());
+        if is_port && host.parse::<Ipv6Addr>().is_ok() {
+            return format!("[{}]:{}", host, port);
+        }
+    }
+    if authority.parse::<Ipv6Addr>().is_ok() {
+        return format!("[{}]", authority);
+    }
+    authority.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejected(addr: &str) -> String {
+        match websocket_url(addr, false) {
+            Err(WebsocketError::InvalidAddress {
+                addr: rejected,
+                reason,
+            }) => {
+                assert_eq!(rejected, addr);
+                reason
+            }
+            other => panic!("expected {:?} to be rejected, got {:?}", addr, other),
+        }
+    }
+
+    #[test]
+    fn accepts_addresses() {
+        let url = |addr| websocket_url(addr, false).unwrap();
+        assert_eq!(url("localhost"), "ws://localhost");
+        assert_eq!(url("localhost:8000"), "ws://localhost:8000");
+        assert_eq!(url("127.0.0.1:8000/chat"), "ws://127.0.0.1:8000/chat");
+        assert_eq!(
+            url("example.com/path?query#x"),
+            "ws://example.com/path?query#x"
+        );
+        assert_eq!(url("my_host-1.example:0"), "ws://my_host-1.example:0");
+        assert_eq!(url("host:65535"), "ws://host:65535");
+    }
+
+    #[test]
+    fn brackets_ipv6() {
+        assert_eq!(bracket_ipv6("::1"), "[::1]");
+        assert_eq!(bracket_ipv6("::1:8080"), "[::1]:8080");
+        assert_eq!(bracket_ipv6("2001:db8::1"), "[2001:db8::1]");
+        // a trailing group is the port if the rest is an address by itself
+        assert_eq!(bracket_ipv6("2001:db8::1:443"), "[2001:db8::1]:443");
+        assert_eq!(bracket_ipv6("[::1]:8080"), "[::1]:8080");
+        assert_eq!(bracket_ipv6("[::1]"), "[::1]");
+        assert_eq!(bracket_ipv6("localhost:8080"), "localhost:8080");
+        assert_eq!(bracket_ipv6("fe80::1%eth0"), "fe80::1%eth0");
+    }
+
+    #[test]
+    fn accepts_ipv6() {
+        let url = |addr| websocket_url(addr, false).unwrap();
+        assert_eq!(url("::1"), "ws://[::1]");
+        assert_eq!(url("::1:8080"), "ws://[::1]:8080");
+        assert_eq!(url("[::1]:8080/chat"), "ws://[::1]:8080/chat");
+        assert_eq!(url("[2001:db8::1]"), "ws://[2001:db8::1]");
+    }
+
+    #[test]
+    fn rejects_invalid_ipv6() {
+        assert!(rejected("fe80::1%eth0").contains("zone"));
+        assert!(rejected("[fe80::1%eth0]:8080").contains("zone"));
+        assert!(rejected("[::1").contains("closing `]`"));
+        assert!(rejected("[::g]").contains("invalid IPv6 address"));
+        assert!(rejected("[::1]8080").contains("after the IPv6 address"));
+        assert!(rejected("[::1]:port").contains("port"));
+    }
+
+    #[test]
+    fn picks_scheme() {
+        assert_eq!(
+            websocket_url("example.com", true).unwrap(),
+            "wss://example.com"
+        );
+        assert_eq!(
+            websocket_url("example.com", false).unwrap(),
+            "ws://example.com"
+        );
+    }
+
+    #[test]
+    fn rejects_schemes() {
+        assert!(rejected("ws://localhost").contains("scheme"));
+        assert!(rejected("wss://localhost:8000").contains("scheme"));
+        assert!(rejected("http://localhost").contains("scheme"));
+    }
+
+    #[test]
+    fn rejects_missing_host() {
+        assert!(rejected("").contains("missing a host"));
+        assert!(rejected(":8000").contains("missing a host"));
+        assert!(rejected("/path").contains("missing a host"));
+    }
+
+    #[test]
+    fn rejects_invalid_addresses() {
+        assert!(rejected("local host").contains("invalid character"));
+        assert!(rejected("localhost\n").contains("invalid character"));
+        assert!(rejected("user:pass@localhost").contains("credentials"));
+        assert!(rejected("exa!mple.com").contains("invalid character"));
+        assert!(rejected("localhost:http").contains("port"));
+        assert!(rejected("localhost:65536").contains("port"));
+        assert!(rejected("localhost:").contains("port"));
+    }
+}
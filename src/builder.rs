@@ -0,0 +1,279 @@
+use std::cell::RefCell;
+use std::time::Duration;
+
+use crate::addr;
+use crate::conflate::Conflation;
+use crate::{CloseCode, CloseReasonPolicy, WebsocketIO};
+
+/// Options applied to a connection, collected by [`WebsocketBuilder`].
+///
+/// Every builder starts from the defaults set with [`set_default_config`], its methods
+/// overriding single options. The fields are documented on the corresponding builder methods.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Config {
+    /// See [`WebsocketBuilder::text_frames`].
+    pub text_frames: TextFrames,
+    /// See [`WebsocketBuilder::max_buffered_bytes`].
+    pub max_buffered: Option<(usize, OverflowPolicy)>,
+    /// See [`WebsocketBuilder::max_message_size`].
+    pub max_message_size: Option<(usize, Option<CloseCode>)>,
+    /// See [`WebsocketBuilder::idle_timeout`].
+    pub idle_timeout: Option<(Duration, CloseCode)>,
+    /// See [`WebsocketBuilder::connect_timeout`].
+    pub connect_timeout: Option<Duration>,
+    /// See [`WebsocketBuilder::max_message_age`].
+    pub max_age: Option<Duration>,
+    /// See [`WebsocketBuilder::conflate`].
+    pub conflation: Option<Conflation>,
+    /// See [`WebsocketBuilder::protocols`].
+    pub protocols: Vec<String>,
+    /// See [`WebsocketBuilder::auth_via_protocol`].
+    pub auth_protocol: Option<String>,
+    /// See [`WebsocketBuilder::write_high_water_mark`].
+    pub write_high_water_mark: Option<usize>,
+    /// See [`WebsocketBuilder::close_reason_policy`].
+    pub close_reason_policy: CloseReasonPolicy,
+    /// See [`WebsocketBuilder::decompress`].
+    #[cfg(feature = "decompress")]
+    pub decompress: bool,
+}
+
+thread_local! {
+    static DEFAULT_CONFIG: RefCell<Config> = RefCell::new(Config::default());
+}
+
+/// Sets the [`Config`] every [`WebsocketBuilder`] created afterwards on this thread starts from.
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use websocket_async_io::{CloseCode, Config};
+/// let mut config = Config::default();
+/// config.idle_timeout = Some((Duration::from_secs(30), CloseCode::GoingAway));
+/// websocket_async_io::set_default_config(config);
+/// ```
+pub fn set_default_config(config: Config) {
+    DEFAULT_CONFIG.with(|default| *default.borrow_mut() = config);
+}
+
+/// What to do with an incoming message that doesn't fit into the read buffer limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drop the message and return an [`OutOfMemory`](std::io::ErrorKind::OutOfMemory) error
+    /// from the next read.
+    Error,
+    /// Silently drop the message.
+    Drop,
+    /// Drop the message, close the connection and return an
+    /// [`OutOfMemory`](std::io::ErrorKind::OutOfMemory) error from the next read, after the
+    /// buffered messages. Browsers can't pause receiving, so this is how to stop accepting data.
+    Close,
+}
+
+/// What the reader does with text frames.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextFrames {
+    /// Silently drop them.
+    #[default]
+    Drop,
+    /// Deliver their UTF-8 bytes in the byte stream like binary messages.
+    Deliver,
+    /// Drop them and return an [`InvalidData`](std::io::ErrorKind::InvalidData) error from the
+    /// next read.
+    Reject,
+}
+
+/// Builder for a [`WebsocketIO`] with non-default options.
+///
+/// ```rust,no_run
+/// # use websocket_async_io::WebsocketIO;
+/// # async fn run() -> Result<(), std::io::Error> {
+/// let ws = WebsocketIO::builder("localhost:8000")
+///     .secure(true)
+///     .strict(true)
+///     .connect()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct WebsocketBuilder {
+    addr: String,
+    secure: bool,
+    config: Config,
+}
+
+impl WebsocketBuilder {
+    pub(crate) fn new(addr: &str) -> WebsocketBuilder {
+        WebsocketBuilder {
+            addr: addr.to_string(),
+            secure: false,
+            config: DEFAULT_CONFIG.with(|default| default.borrow().clone()),
+        }
+    }
+
+    /// Connect using `wss://` instead of `ws://`.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Reject text frames with an [`InvalidData`](std::io::ErrorKind::InvalidData) error on the
+    /// reader instead of silently dropping them, a shorthand for
+    /// [`text_frames`](Self::text_frames).
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.config.text_frames = if strict {
+            TextFrames::Reject
+        } else {
+            TextFrames::Drop
+        };
+        self
+    }
+
+    /// What the reader does with text frames, dropping them by default.
+    pub fn text_frames(mut self, policy: TextFrames) -> Self {
+        self.config.text_frames = policy;
+        self
+    }
+
+    /// Limit the bytes held by the reader that the application hasn't read yet.
+    ///
+    /// Messages arriving while the limit would be exceeded are dropped according to `policy`.
+    /// This bounds the memory of received but unread data, including the unread rest of a message
+    /// partially read.
+    pub fn max_buffered_bytes(mut self, limit: usize, policy: OverflowPolicy) -> Self {
+        self.config.max_buffered = Some((limit, policy));
+        self
+    }
+
+    /// Drop received messages larger than `limit` bytes and return an
+    /// [`InvalidData`](std::io::ErrorKind::InvalidData) error from the next read, optionally
+    /// closing the connection with `close`.
+    ///
+    /// Browsers can't send [`CloseCode::MessageTooBig`], so use an application code for `close`.
+    /// Codes that can't be sent close the connection without a code.
+    pub fn max_message_size(mut self, limit: usize, close: Option<CloseCode>) -> Self {
+        self.config.max_message_size = Some((limit, close));
+        self
+    }
+
+    /// Close the connection with the close `code` after no message was received and nothing was
+    /// written for `timeout`.
+    ///
+    /// The reader reports the idle close as a [`TimedOut`](std::io::ErrorKind::TimedOut) error,
+    /// after the messages received before it.
+    pub fn idle_timeout(mut self, timeout: Duration, code: CloseCode) -> Self {
+        self.config.idle_timeout = Some((timeout, code));
+        self
+    }
+
+    /// Fail [`connect`](Self::connect) with a [`TimedOut`](std::io::ErrorKind::TimedOut) error
+    /// if the opening handshake didn't complete within `timeout`.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.config.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Discard received messages that waited longer than `max_age` for the application to read
+    /// them, for real-time feeds where old data is useless.
+    ///
+    /// The number of discarded messages is available from
+    /// [`WebsocketReader::stale_dropped`](crate::WebsocketReader::stale_dropped).
+    pub fn max_message_age(mut self, max_age: Duration) -> Self {
+        self.config.max_age = Some(max_age);
+        self
+    }
+
+    /// Conflate received messages the application hasn't read yet, keeping only the newest
+    /// message for every key extracted by `key`.
+    ///
+    /// A replaced message's slot in the queue is taken by its successor. Messages for which `key`
+    /// returns `None` are never conflated. The number of replaced messages is available from
+    /// [`WebsocketReader::conflated`](crate::WebsocketReader::conflated).
+    ///
+    /// ```rust,no_run
+    /// # use websocket_async_io::WebsocketIO;
+    /// # async fn run() -> Result<(), std::io::Error> {
+    /// // the first four bytes of every message are an instrument id
+    /// let ws = WebsocketIO::builder("localhost:8000")
+    ///     .conflate(|message| message.get(..4).map(<[u8]>::to_vec))
+    ///     .connect()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn conflate<K, F>(mut self, key: F) -> Self
+    where
+        K: std::hash::Hash + Eq + 'static,
+        F: Fn(&[u8]) -> Option<K> + 'static,
+    {
+        self.config.conflation = Some(Conflation::new(key));
+        self
+    }
+
+    /// Offer these subprotocols in the `Sec-WebSocket-Protocol` header.
+    ///
+    /// The one picked by the server is available from [`WebsocketIO::protocol`].
+    pub fn protocols<I, S>(mut self, protocols: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.protocols = protocols.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Send `token` as an additional entry of the `Sec-WebSocket-Protocol` header.
+    ///
+    /// Browsers don't allow setting headers on websocket connections, so servers like the
+    /// Kubernetes API accept credentials smuggled through the protocol list instead.
+    /// The token must be a valid HTTP token (e.g. base64url encoded, optionally with a prefix
+    /// the server expects) or connecting fails.
+    ///
+    /// The server has to pick one of the offered protocols in its response, otherwise browsers
+    /// abort the connection. It should prefer one from [`protocols`](Self::protocols), but may
+    /// echo the token itself, which [`WebsocketIO::protocol`] then hides.
+    ///
+    /// Keep in mind that the protocol list is visible to proxies and in server logs just like
+    /// other headers.
+    pub fn auth_via_protocol(mut self, token: impl Into<String>) -> Self {
+        self.config.auth_protocol = Some(token.into());
+        self
+    }
+
+    /// Make writes wait while the browser buffers at least `limit` bytes that weren't sent yet
+    /// (`bufferedAmount`), instead of letting its send buffer grow unbounded on slow links.
+    ///
+    /// Writes check again every few milliseconds, as browsers don't signal a draining buffer.
+    pub fn write_high_water_mark(mut self, limit: usize) -> Self {
+        self.config.write_high_water_mark = Some(limit);
+        self
+    }
+
+    /// What [`WebsocketWriter::close_with`](crate::WebsocketWriter::close_with) does with close
+    /// reasons exceeding 123 bytes, truncating them by default.
+    pub fn close_reason_policy(mut self, policy: CloseReasonPolicy) -> Self {
+        self.config.close_reason_policy = policy;
+        self
+    }
+
+    /// Detect gzip, zlib and zstd compressed messages by their header and decompress them
+    /// before they are read.
+    ///
+    /// gzip and zstd messages that fail to decompress produce an
+    /// [`InvalidData`](std::io::ErrorKind::InvalidData) error. zlib headers are only two bytes
+    /// that raw payloads can start with as well, so those messages are delivered unchanged. The
+    /// [`max_message_size`](Self::max_message_size) and
+    /// [`max_buffered_bytes`](Self::max_buffered_bytes) limits also apply to the decompressed
+    /// size, decoding stops as soon as a message exceeds them.
+    #[cfg(feature = "decompress")]
+    pub fn decompress(mut self, decompress: bool) -> Self {
+        self.config.decompress = decompress;
+        self
+    }
+
+    pub async fn connect(self) -> Result<WebsocketIO, std::io::Error> {
+        let url = addr::websocket_url(&self.addr, self.secure)?;
+        WebsocketIO::new_inner(&url, self.config).await
+    }
+}
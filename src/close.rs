@@ -0,0 +1,306 @@
+use std::cell::RefCell;
+use std::future::Future;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+use web_sys::WebSocket;
+
+use crate::WebsocketError;
+
+/// Websocket close codes as defined in [RFC 6455, section 7.4](https://datatracker.ietf.org/doc/html/rfc6455#section-7.4).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CloseCode {
+    /// 1000, the purpose of the connection has been fulfilled.
+    Normal,
+    /// 1001, the endpoint is going away, e.g. a server shutting down or a page being navigated away from.
+    GoingAway,
+    /// 1002, the connection is terminated because of a protocol error.
+    ProtocolError,
+    /// 1003, the endpoint received a type of data it cannot accept.
+    Unsupported,
+    /// 1005, no status code was present. Never sent over the wire.
+    NoStatus,
+    /// 1006, the connection was closed without a close frame. Never sent over the wire.
+    Abnormal,
+    /// 1007, a message contained data inconsistent with its type.
+    InvalidData,
+    /// 1008, a message violated the endpoint's policy.
+    PolicyViolation,
+    /// 1009, a message was too big to process.
+    MessageTooBig,
+    /// 1010, the server didn't negotiate an extension the client requires.
+    MandatoryExtension,
+    /// 1011, the server encountered an unexpected condition.
+    InternalError,
+    /// 1012, the server is restarting.
+    ServiceRestart,
+    /// 1013, the server is overloaded, try again later.
+    TryAgainLater,
+    /// 1014, a gateway received an invalid response from the upstream server.
+    BadGateway,
+    /// 1015, the TLS handshake failed. Never sent over the wire.
+    TlsHandshake,
+    /// 3000-3999, registered for use by libraries and frameworks.
+    Library(u16),
+    /// 4000-4999, reserved for private use by applications.
+    Application(u16),
+    /// Any other code, reserved by the specification.
+    Reserved(u16),
+}
+
+impl CloseCode {
+    /// Whether browsers allow sending this code with `WebSocket.close()`.
+    ///
+    /// Only [`Normal`](CloseCode::Normal), [`Library`](CloseCode::Library) and
+    /// [`Application`](CloseCode::Application) codes are accepted, the latter two only within
+    /// their ranges as they can be constructed with any code.
+    pub fn is_sendable(self) -> bool {
+        match self {
+            CloseCode::Normal => true,
+            CloseCode::Library(code) => (3000..=3999).contains(&code),
+            CloseCode::Application(code) => (4000..=4999).contains(&code),
+            _ => false,
+        }
+    }
+}
+
+impl From<u16> for CloseCode {
+    fn from(code: u16) -> CloseCode {
+        match code {
+            1000 => CloseCode::Normal,
+            1001 => CloseCode::GoingAway,
+            1002 => CloseCode::ProtocolError,
+            1003 => CloseCode::Unsupported,
+            1005 => CloseCode::NoStatus,
+            1006 => CloseCode::Abnormal,
+            1007 => CloseCode::InvalidData,
+            1008 => CloseCode::PolicyViolation,
+            1009 => CloseCode::MessageTooBig,
+            1010 => CloseCode::MandatoryExtension,
+            1011 => CloseCode::InternalError,
+            1012 => CloseCode::ServiceRestart,
+            1013 => CloseCode::TryAgainLater,
+            1014 => CloseCode::BadGateway,
+            1015 => CloseCode::TlsHandshake,
+            3000..=3999 => CloseCode::Library(code),
+            4000..=4999 => CloseCode::Application(code),
+            _ => CloseCode::Reserved(code),
+        }
+    }
+}
+
+impl From<CloseCode> for u16 {
+    fn from(code: CloseCode) -> u16 {
+        match code {
+            CloseCode::Normal => 1000,
+            CloseCode::GoingAway => 1001,
+            CloseCode::ProtocolError => 1002,
+            CloseCode::Unsupported => 1003,
+            CloseCode::NoStatus => 1005,
+            CloseCode::Abnormal => 1006,
+            CloseCode::InvalidData => 1007,
+            CloseCode::PolicyViolation => 1008,
+            CloseCode::MessageTooBig => 1009,
+            CloseCode::MandatoryExtension => 1010,
+            CloseCode::InternalError => 1011,
+            CloseCode::ServiceRestart => 1012,
+            CloseCode::TryAgainLater => 1013,
+            CloseCode::BadGateway => 1014,
+            CloseCode::TlsHandshake => 1015,
+            CloseCode::Library(code) | CloseCode::Application(code) | CloseCode::Reserved(code) => {
+                code
+            }
+        }
+    }
+}
+
+/// How a connection was closed, from the browser's `CloseEvent`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CloseInfo {
+    pub code: CloseCode,
+    pub reason: String,
+    /// Whether the closing handshake completed. `false` when the connection dropped.
+    pub was_clean: bool,
+}
+
+/// Shared slot for the [`CloseInfo`] of a connection, resolving every waiting future once set.
+#[derive(Clone, Default)]
+pub(crate) struct CloseSignal(Rc<RefCell<CloseSignalState>>);
+
+#[derive(Default)]
+struct CloseSignalState {
+    info: Option<CloseInfo>,
+    wakers: Vec<Waker>,
+    /// the error ending the reader, set when this side closed the connection
+    local: Option<Option<WebsocketError>>,
+    /// whether this side closed the connection for being idle, kept after `local` was taken
+    idle: bool,
+}
+
+impl CloseSignal {
+    pub(crate) fn set(&self, info: CloseInfo) {
+        let wakers = {
+            let mut state = self.0.borrow_mut();
+            state.info = Some(info);
+            std::mem::take(&mut state.wakers)
+        };
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    pub(crate) fn poll(&self, cx: &mut Context<'_>) -> Poll<CloseInfo> {
+        let mut state = self.0.borrow_mut();
+        match &state.info {
+            Some(info) => Poll::Ready(info.clone()),
+            None => {
+                if !state.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                    state.wakers.push(cx.waker().clone());
+                }
+                Poll::Pending
+            }
+        }
+    }
+
+    /// How the connection closed, `None` while it's open.
+    pub(crate) fn info(&self) -> Option<CloseInfo> {
+        self.0.borrow().info.clone()
+    }
+
+    /// Records that this side closed the connection, so the `close` event ends the reader with
+    /// `error` instead of an error derived from the close code. The first call wins.
+    pub(crate) fn close_locally(&self, error: Option<WebsocketError>) {
+        let mut state = self.0.borrow_mut();
+        if state.local.is_none() {
+            state.idle = matches!(error, Some(WebsocketError::IdleTimeout(_)));
+            state.local = Some(error);
+        }
+    }
+
+    /// Whether this side closed the connection after it was idle for too long.
+    pub(crate) fn was_idle(&self) -> bool {
+        self.0.borrow().idle
+    }
+
+    /// How the reader ends if this side closed the connection, `None` if the peer did.
+    pub(crate) fn take_local(&self) -> Option<Option<WebsocketError>> {
+        self.0.borrow_mut().local.take()
+    }
+
+    pub(crate) fn wait(&self) -> impl Future<Output = CloseInfo> + 'static {
+        let signal = self.clone();
+        std::future::poll_fn(move |cx| signal.poll(cx))
+    }
+}
+
+/// The maximum length of a close reason in bytes, as it has to fit into a control frame.
+pub(crate) const MAX_REASON_BYTES: usize = 123;
+
+/// What [`close_with`](crate::WebsocketWriter::close_with) does with a reason longer than
+/// 123 bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CloseReasonPolicy {
+    /// Cut the reason at the last character boundary that fits.
+    #[default]
+    Truncate,
+    /// Fail with [`WebsocketError::ReasonTooLong`](crate::WebsocketError::ReasonTooLong).
+    Error,
+}
+
+impl CloseReasonPolicy {
+    /// The reason to send for `reason`, or the error to fail with.
+    pub(crate) fn apply(self, reason: &str) -> Result<&str, WebsocketError> {
+        match self {
+            CloseReasonPolicy::Truncate => Ok(truncate_reason(reason)),
+            CloseReasonPolicy::Error if reason.len() > MAX_REASON_BYTES => {
+                Err(WebsocketError::ReasonTooLong(reason.len()))
+            }
+            CloseReasonPolicy::Error => Ok(reason),
+        }
+    }
+}
+
+/// Shortens `reason` to at most [`MAX_REASON_BYTES`] without splitting a character.
+pub(crate) fn truncate_reason(reason: &str) -> &str {
+    if reason.len() <= MAX_REASON_BYTES {
+        return reason;
+    }
+    let end = (0..=MAX_REASON_BYTES)
+        .rev()
+        .find(|&i| reason.is_char_boundary(i))
+        .unwrap_or(0);
+    &reason[..end]
+}
+
+/// Starts the closing handshake with `code` and `reason`, applying the reason `policy`.
+pub(crate) fn close_with(
+    ws: &WebSocket,
+    signal: &CloseSignal,
+    policy: CloseReasonPolicy,
+    code: CloseCode,
+    reason: &str,
+) -> std::io::Result<()> {
+    if !code.is_sendable() {
+        return Err(WebsocketError::InvalidCloseCode(code).into());
+    }
+    let reason = policy.apply(reason)?;
+
+    ws.close_with_code_and_reason(code.into(), reason)
+        .map_err(|e| std::io::Error::from(WebsocketError::js(e)))?;
+    signal.close_locally(None);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncates_at_char_boundary() {
+        let short = "ä".repeat(61);
+        assert_eq!(truncate_reason(&short), short);
+
+        // 124 bytes, the 62nd two byte character crosses the limit
+        let long = "ä".repeat(62);
+        assert_eq!(truncate_reason(&long), "ä".repeat(61));
+
+        // 124 bytes, the last three byte character starts at byte 121
+        let long = format!("a{}", "€".repeat(41));
+        let truncated = truncate_reason(&long);
+        assert_eq!(truncated.len(), 121);
+        assert_eq!(truncated, format!("a{}", "€".repeat(40)));
+
+        let exact = "€".repeat(41);
+        assert_eq!(exact.len(), MAX_REASON_BYTES);
+        assert_eq!(truncate_reason(&exact), exact);
+    }
+
+    #[test]
+    fn applies_reason_policy() {
+        let long = format!("a{}", "€".repeat(41));
+        let truncated = CloseReasonPolicy::Truncate.apply(&long).unwrap();
+        assert_eq!(truncated, format!("a{}", "€".repeat(40)));
+
+        match CloseReasonPolicy::Error.apply(&long) {
+            Err(WebsocketError::ReasonTooLong(len)) => assert_eq!(len, 124),
+            other => panic!("expected ReasonTooLong, got {:?}", other),
+        }
+
+        let exact = "€".repeat(41);
+        assert_eq!(CloseReasonPolicy::Error.apply(&exact).unwrap(), exact);
+        assert_eq!(CloseReasonPolicy::Truncate.apply("bye").unwrap(), "bye");
+    }
+
+    #[test]
+    fn sendable_codes() {
+        assert!(CloseCode::Normal.is_sendable());
+        assert!(CloseCode::Library(3000).is_sendable());
+        assert!(CloseCode::Application(4999).is_sendable());
+        assert!(CloseCode::from(4401).is_sendable());
+
+        assert!(!CloseCode::GoingAway.is_sendable());
+        assert!(!CloseCode::Reserved(2000).is_sendable());
+        assert!(!CloseCode::Library(1000).is_sendable());
+        assert!(!CloseCode::Library(4000).is_sendable());
+        assert!(!CloseCode::Application(3999).is_sendable());
+        assert!(!CloseCode::Application(5000).is_sendable());
+    }
+}
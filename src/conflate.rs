@@ -0,0 +1,93 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::hash::Hash;
+use std::rc::Rc;
+
+use crate::Message;
+
+/// Queue of received messages keeping only the newest message per key.
+pub(crate) trait ConflatingQueue {
+    /// Enqueues `message`, returning the older message with the same key it replaced.
+    fn push(&mut self, message: Message) -> Option<Message>;
+    fn pop(&mut self) -> Option<Message>;
+}
+
+struct KeyedQueue<K, F> {
+    key: Rc<F>,
+    queue: VecDeque<(Option<Rc<K>>, Message)>,
+    /// position of every queued key, counting all messages ever queued
+    positions: HashMap<Rc<K>, u64>,
+    /// the number of popped messages, which is the position of the queue's front
+    popped: u64,
+}
+
+impl<K, F> ConflatingQueue for KeyedQueue<K, F>
+where
+    K: Hash + Eq,
+    F: Fn(&[u8]) -> Option<K>,
+{
+    fn push(&mut self, message: Message) -> Option<Message> {
+        let key = match (self.key)(&message.data.to_vec()) {
+            Some(key) => key,
+            None => {
+                self.queue.push_back((None, message));
+                return None;
+            }
+        };
+
+        if let Some(&position) = self.positions.get(&key) {
+            // keep the position of the older message so other keys aren't starved
+            let (_, queued) = &mut self.queue[(position - self.popped) as usize];
+            return Some(std::mem::replace(queued, message));
+        }
+
+        let key = Rc::new(key);
+        let position = self.popped + self.queue.len() as u64;
+        self.positions.insert(Rc::clone(&key), position);
+        self.queue.push_back((Some(key), message));
+        None
+    }
+
+    fn pop(&mut self) -> Option<Message> {
+        let (key, message) = self.queue.pop_front()?;
+        self.popped += 1;
+        if let Some(key) = key {
+            self.positions.remove(&*key);
+        }
+        Some(message)
+    }
+}
+
+/// Creates the conflating queue for each connection built from a [`Config`](crate::Config).
+#[derive(Clone)]
+pub struct Conflation(Rc<dyn Fn() -> Box<dyn ConflatingQueue>>);
+
+impl Conflation {
+    /// Conflates messages by the key extracted by `key`, see
+    /// [`WebsocketBuilder::conflate`](crate::WebsocketBuilder::conflate).
+    pub fn new<K, F>(key: F) -> Conflation
+    where
+        K: Hash + Eq + 'static,
+        F: Fn(&[u8]) -> Option<K> + 'static,
+    {
+        let key = Rc::new(key);
+        Conflation(Rc::new(move || {
+            Box::new(KeyedQueue {
+                key: Rc::clone(&key),
+                queue: VecDeque::new(),
+                positions: HashMap::new(),
+                popped: 0,
+            })
+        }))
+    }
+
+    pub(crate) fn queue(&self) -> Box<dyn ConflatingQueue> {
+        (self.0)()
+    }
+}
+
+impl fmt::Debug for Conflation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Conflation").finish_non_exhaustive()
+    }
+}
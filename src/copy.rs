@@ -0,0 +1,79 @@
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use futures_io::{AsyncBufRead, AsyncWrite};
+
+use crate::{WebsocketIO, WebsocketReader, WebsocketWriter};
+
+/// Relays data between two websocket connections until both directions reached EOF.
+///
+/// Each received message is forwarded as one message on the other connection. When one side
+/// closes, the other side's writer is closed as well. Returns the number of bytes sent from `a`
+/// to `b` and from `b` to `a`, or the first error of either direction, after closing both
+/// connections.
+pub async fn copy_bidirectional(a: WebsocketIO, b: WebsocketIO) -> std::io::Result<(u64, u64)> {
+    let (mut a_reader, mut a_writer) = a.split();
+    let (mut b_reader, mut b_writer) = b.split();
+    let mut a_to_b = Relay::default();
+    let mut b_to_a = Relay::default();
+
+    let mut error = None;
+    std::future::poll_fn(|cx| {
+        if error.is_none() {
+            let a_to_b_done = a_to_b.poll_relay(cx, &mut a_reader, &mut b_writer);
+            let b_to_a_done = b_to_a.poll_relay(cx, &mut b_reader, &mut a_writer);
+            match (a_to_b_done, b_to_a_done) {
+                (Poll::Ready(Err(e)), _) | (_, Poll::Ready(Err(e))) => error = Some(e),
+                (Poll::Ready(Ok(())), Poll::Ready(Ok(()))) => {
+                    return Poll::Ready(Ok((a_to_b.total, b_to_a.total)))
+                }
+                _ => return Poll::Pending,
+            }
+        }
+
+        // after an error neither connection is used anymore, so both are closed before returning
+        let a_closed = Pin::new(&mut a_writer).poll_close(cx);
+        let b_closed = Pin::new(&mut b_writer).poll_close(cx);
+        match (a_closed, b_closed) {
+            (Poll::Ready(_), Poll::Ready(_)) => Poll::Ready(Err(error.take().unwrap())),
+            _ => Poll::Pending,
+        }
+    })
+    .await
+}
+
+#[derive(Default)]
+struct Relay {
+    total: u64,
+    done: bool,
+}
+
+impl Relay {
+    fn poll_relay(
+        &mut self,
+        cx: &mut Context<'_>,
+        reader: &mut WebsocketReader,
+        writer: &mut WebsocketWriter,
+    ) -> Poll<std::io::Result<()>> {
+        if self.done {
+            return Poll::Ready(Ok(()));
+        }
+
+        loop {
+            // the reader's buffer holds the rest of exactly one message
+            let buf = ready!(Pin::new(&mut *reader).poll_fill_buf(cx))?;
+            if buf.is_empty() {
+                ready!(Pin::new(&mut *writer).poll_close(cx))?;
+                self.done = true;
+                return Poll::Ready(Ok(()));
+            }
+
+            let n = ready!(Pin::new(&mut *writer).poll_write(cx, buf))?;
+            if n == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            Pin::new(&mut *reader).consume(n);
+            self.total += n as u64;
+        }
+    }
+}
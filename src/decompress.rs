@@ -0,0 +1,133 @@
+use std::io::Read;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Decompresses `data` if it starts with a gzip, zstd or zlib header.
+///
+/// Returns `None` for messages that don't look compressed. zlib has no real magic bytes, so its
+/// detection relies on the header checksum and can misfire on raw payloads starting with `0x78`.
+/// Those fail to inflate and are returned as `None` as well, while corrupt gzip and zstd
+/// messages are errors.
+///
+/// Decoding stops after `limit + 1` bytes, so a small message can't expand into an arbitrarily
+/// large one. A result longer than `limit` means the output didn't fit.
+pub(crate) fn sniff_and_decompress(data: &[u8], limit: usize) -> std::io::Result<Option<Vec<u8>>> {
+    let limit = (limit as u64).saturating_add(1);
+    let mut decoded = Vec::new();
+
+    if data.starts_with(&GZIP_MAGIC) {
+        flate2::read::GzDecoder::new(data)
+            .take(limit)
+            .read_to_end(&mut decoded)?;
+    } else if data.starts_with(&ZSTD_MAGIC) {
+        ruzstd::decoding::StreamingDecoder::new(data)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?
+            .take(limit)
+            .read_to_end(&mut decoded)?;
+    } else if is_zlib_header(data) {
+        let inflated = flate2::read::ZlibDecoder::new(data)
+            .take(limit)
+            .read_to_end(&mut decoded);
+        if inflated.is_err() {
+            return Ok(None);
+        }
+    } else {
+        return Ok(None);
+    }
+
+    Ok(Some(decoded))
+}
+
+fn is_zlib_header(data: &[u8]) -> bool {
+    match data {
+        // deflate with a 32K window, no preset dictionary, valid header checksum
+        [cmf, flg, ..] => {
+            *cmf == 0x78 && flg & 0x20 == 0 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    const TEXT: &[u8] = b"hello hello hello hello, compressed world";
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let mut encoder =
+            flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn zstd(data: &[u8]) -> Vec<u8> {
+        ruzstd::encoding::compress_to_vec(data, ruzstd::encoding::CompressionLevel::Fastest)
+    }
+
+    #[test]
+    fn sniffs_headers() {
+        assert!(gzip(TEXT).starts_with(&GZIP_MAGIC));
+        assert!(zstd(TEXT).starts_with(&ZSTD_MAGIC));
+        assert!(is_zlib_header(&zlib(TEXT)));
+
+        assert!(!is_zlib_header(b"x"));
+        assert!(!is_zlib_header(b"xy"));
+        assert!(!is_zlib_header(&[0x78, 0x20]));
+    }
+
+    #[test]
+    fn passes_through_uncompressed() {
+        assert_eq!(sniff_and_decompress(TEXT, usize::MAX).unwrap(), None);
+        assert_eq!(sniff_and_decompress(&[], usize::MAX).unwrap(), None);
+    }
+
+    #[test]
+    fn passes_through_raw_payload_with_zlib_header() {
+        // `x^` is a valid zlib header, the rest isn't deflate
+        let raw = b"x^ raw payload";
+        assert!(is_zlib_header(raw));
+        assert_eq!(sniff_and_decompress(raw, usize::MAX).unwrap(), None);
+    }
+
+    #[test]
+    fn round_trips() {
+        for compressed in [gzip(TEXT), zlib(TEXT), zstd(TEXT)] {
+            let decoded = sniff_and_decompress(&compressed, usize::MAX).unwrap();
+            assert_eq!(decoded.as_deref(), Some(TEXT));
+        }
+    }
+
+    #[test]
+    fn stops_at_limit() {
+        let bomb = vec![0; 1 << 20];
+        for compressed in [gzip(&bomb), zlib(&bomb), zstd(&bomb)] {
+            assert!(compressed.len() < bomb.len() / 100);
+            let decoded = sniff_and_decompress(&compressed, 100).unwrap().unwrap();
+            assert_eq!(decoded.len(), 101);
+        }
+
+        let decoded = sniff_and_decompress(&gzip(TEXT), TEXT.len()).unwrap();
+        assert_eq!(decoded.as_deref(), Some(TEXT));
+    }
+
+    #[test]
+    fn rejects_corrupt_data() {
+        let mut compressed = gzip(TEXT);
+        compressed.truncate(compressed.len() / 2);
+        assert!(sniff_and_decompress(&compressed, usize::MAX).is_err());
+
+        let mut compressed = zstd(TEXT);
+        compressed.truncate(compressed.len() / 2);
+        assert!(sniff_and_decompress(&compressed, usize::MAX).is_err());
+    }
+}
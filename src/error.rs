@@ -0,0 +1,214 @@
+use std::fmt;
+use std::time::Duration;
+
+use wasm_bindgen::{JsCast, JsValue};
+
+use crate::close::MAX_REASON_BYTES;
+use crate::CloseCode;
+
+/// Errors produced by this crate.
+///
+/// All fallible APIs return [`std::io::Error`]s to fit into the `AsyncRead`/`AsyncWrite`
+/// ecosystem. Errors originating in this crate carry a `WebsocketError`, which can be retrieved
+/// with [`WebsocketError::from_io`]:
+///
+/// ```rust
+/// # use websocket_async_io::WebsocketError;
+/// # fn handle(error: std::io::Error) {
+/// match WebsocketError::from_io(&error) {
+///     Some(WebsocketError::IdleTimeout(_)) => { /* reconnect when needed */ }
+///     Some(WebsocketError::Decode(_)) => { /* report a misbehaving server */ }
+///     _ => { /* something else */ }
+/// }
+/// # }
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum WebsocketError {
+    /// The WebSocket API is not available in this environment.
+    Unsupported,
+    /// The address passed to [`WebsocketIO::builder`](crate::WebsocketIO::builder) is malformed.
+    InvalidAddress { addr: String, reason: String },
+    /// The browser refused to create the connection, e.g. because of a malformed url or a
+    /// blocked port.
+    Connect { name: String, message: String },
+    /// The server could not be reached or rejected the opening handshake.
+    ///
+    /// Browsers don't tell these apart, so both are reported as a
+    /// [`ConnectionRefused`](std::io::ErrorKind::ConnectionRefused) error.
+    HandshakeRejected(String),
+    /// Connecting failed while the browser reported being offline, as a
+    /// [`HostUnreachable`](std::io::ErrorKind::HostUnreachable) error.
+    Offline,
+    /// The opening handshake didn't complete within the configured timeout.
+    ConnectTimeout(Duration),
+    /// The connection was closed by the server or the network without a normal closing
+    /// handshake. Clean closes, and closes this side started that completed, end the reader
+    /// instead.
+    Closed { code: CloseCode, reason: String },
+    /// The browser reported an error on the connection. Browsers hide the cause, so this rarely
+    /// contains more than a generic message.
+    Socket(String),
+    /// The operation requires an open connection.
+    NotConnected,
+    /// A message was written after the connection started closing.
+    WriteClosed,
+    /// The browser failed to send a message.
+    Send { name: String, message: String },
+    /// A received message could not be decoded, e.g. an unexpected text frame.
+    Decode(String),
+    /// A `size` byte message exceeding the maximum message size of `limit` bytes was dropped.
+    MessageTooBig { size: usize, limit: usize },
+    /// A message was dropped because the reader already buffers `limit` bytes.
+    BufferFull { size: usize, limit: usize },
+    /// The connection was closed after being idle for the configured timeout.
+    IdleTimeout(Duration),
+    /// The close code can't be sent by a browser, see [`CloseCode::is_sendable`].
+    InvalidCloseCode(CloseCode),
+    /// The close reason is longer than the 123 bytes allowed, containing its length.
+    ReasonTooLong(usize),
+    /// Any other exception thrown by a browser API.
+    Js { name: String, message: String },
+}
+
+impl WebsocketError {
+    /// Returns the `WebsocketError` inside an error returned by this crate.
+    pub fn from_io(error: &std::io::Error) -> Option<&WebsocketError> {
+        error.get_ref()?.downcast_ref()
+    }
+
+    /// Converts an exception thrown by a browser API.
+    pub(crate) fn js(error: JsValue) -> WebsocketError {
+        let (name, message) = exception(error);
+        WebsocketError::Js { name, message }
+    }
+
+    fn kind(&self) -> std::io::ErrorKind {
+        use std::io::ErrorKind;
+
+        match self {
+            WebsocketError::Unsupported => ErrorKind::Unsupported,
+            WebsocketError::Connect { name, .. } | WebsocketError::Js { name, .. } => {
+                exception_kind(name)
+            }
+            WebsocketError::HandshakeRejected(_) => ErrorKind::ConnectionRefused,
+            WebsocketError::Offline => ErrorKind::HostUnreachable,
+            WebsocketError::Closed {
+                code: CloseCode::Abnormal,
+                ..
+            } => ErrorKind::ConnectionReset,
+            WebsocketError::Closed { .. } => ErrorKind::ConnectionAborted,
+            WebsocketError::Socket(_) => ErrorKind::ConnectionReset,
+            WebsocketError::NotConnected => ErrorKind::NotConnected,
+            WebsocketError::WriteClosed | WebsocketError::Send { .. } => ErrorKind::BrokenPipe,
+            WebsocketError::Decode(_) | WebsocketError::MessageTooBig { .. } => {
+                ErrorKind::InvalidData
+            }
+            WebsocketError::BufferFull { .. } => ErrorKind::OutOfMemory,
+            WebsocketError::ConnectTimeout(_) | WebsocketError::IdleTimeout(_) => {
+                ErrorKind::TimedOut
+            }
+            WebsocketError::InvalidAddress { .. }
+            | WebsocketError::InvalidCloseCode(_)
+            | WebsocketError::ReasonTooLong(_) => ErrorKind::InvalidInput,
+        }
+    }
+}
+
+impl fmt::Display for WebsocketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebsocketError::Unsupported => {
+                write!(f, "the WebSocket API is not available in this environment")
+            }
+            WebsocketError::InvalidAddress { addr, reason } => {
+                write!(f, "invalid address {:?}: {}", addr, reason)
+            }
+            WebsocketError::Connect { name, message } => {
+                write!(f, "failed to connect: {}: {}", name, message)
+            }
+            WebsocketError::HandshakeRejected(reason) => {
+                write!(f, "websocket handshake failed: {}", reason)
+            }
+            WebsocketError::Offline => write!(f, "failed to connect, the browser is offline"),
+            WebsocketError::ConnectTimeout(timeout) => {
+                write!(
+                    f,
+                    "websocket handshake didn't complete within {:?}",
+                    timeout
+                )
+            }
+            WebsocketError::Closed { code, reason } if reason.is_empty() => {
+                write!(f, "websocket closed with code {}", u16::from(*code))
+            }
+            WebsocketError::Closed { code, reason } => {
+                write!(
+                    f,
+                    "websocket closed with code {}: {}",
+                    u16::from(*code),
+                    reason
+                )
+            }
+            WebsocketError::Socket(message) => write!(f, "websocket error: {}", message),
+            WebsocketError::NotConnected => write!(f, "websocket is not connected"),
+            WebsocketError::WriteClosed => write!(f, "websocket is closing or closed"),
+            WebsocketError::Send { name, message } => {
+                write!(f, "failed to send message: {}: {}", name, message)
+            }
+            WebsocketError::Decode(reason) => write!(f, "failed to decode message: {}", reason),
+            WebsocketError::MessageTooBig { size, limit } => write!(
+                f,
+                "dropped a {} byte message, at most {} bytes are allowed",
+                size, limit
+            ),
+            WebsocketError::BufferFull { size, limit } => write!(
+                f,
+                "dropped a {} byte message, more than {} bytes are buffered",
+                size, limit
+            ),
+            WebsocketError::IdleTimeout(timeout) => {
+                write!(f, "connection closed after being idle for {:?}", timeout)
+            }
+            WebsocketError::InvalidCloseCode(code) => {
+                write!(
+                    f,
+                    "close code {} can't be sent by a browser",
+                    u16::from(*code)
+                )
+            }
+            WebsocketError::ReasonTooLong(len) => write!(
+                f,
+                "close reason is {} bytes long, at most {} are allowed",
+                len, MAX_REASON_BYTES
+            ),
+            WebsocketError::Js { name, message } => write!(f, "{}: {}", name, message),
+        }
+    }
+}
+
+impl std::error::Error for WebsocketError {}
+
+impl From<WebsocketError> for std::io::Error {
+    fn from(error: WebsocketError) -> std::io::Error {
+        std::io::Error::new(error.kind(), error)
+    }
+}
+
+/// Splits an exception thrown by a browser API into its name and message.
+pub(crate) fn exception(error: JsValue) -> (String, String) {
+    match error.dyn_ref::<js_sys::Error>() {
+        Some(error) => (String::from(error.name()), String::from(error.message())),
+        None => (String::from("Error"), format!("{:?}", error)),
+    }
+}
+
+fn exception_kind(name: &str) -> std::io::ErrorKind {
+    match name {
+        // malformed urls, invalid protocol lists, invalid close codes and reasons
+        "SyntaxError" | "InvalidAccessError" => std::io::ErrorKind::InvalidInput,
+        // e.g. blocked ports or insecure connections from secure contexts
+        "SecurityError" => std::io::ErrorKind::PermissionDenied,
+        "InvalidStateError" => std::io::ErrorKind::NotConnected,
+        _ => std::io::ErrorKind::Other,
+    }
+}
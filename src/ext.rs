@@ -0,0 +1,98 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use futures_core::Stream;
+use futures_io::AsyncBufRead;
+
+/// Message-oriented helpers for buffered readers.
+///
+/// For a [`WebsocketReader`](crate::WebsocketReader), a message is the rest of the current
+/// websocket message, exactly as it was received. Other readers fall back to treating whatever
+/// [`poll_fill_buf`](AsyncBufRead::poll_fill_buf) returns as one message, which makes the
+/// helpers usable with wrappers and test doubles as well.
+pub trait WsBufReadExt: AsyncBufRead {
+    /// Reads the next message, returning `None` at EOF.
+    fn read_message(&mut self) -> ReadMessage<'_, Self>
+    where
+        Self: Unpin,
+    {
+        ReadMessage { reader: self }
+    }
+
+    /// Discards the next message, returning its length. Returns `0` at EOF.
+    fn skip_message(&mut self) -> SkipMessage<'_, Self>
+    where
+        Self: Unpin,
+    {
+        SkipMessage { reader: self }
+    }
+
+    /// Turns the reader into a stream of messages ending at EOF.
+    fn messages(self) -> Messages<Self>
+    where
+        Self: Sized + Unpin,
+    {
+        Messages { reader: self }
+    }
+}
+
+impl<R: AsyncBufRead + ?Sized> WsBufReadExt for R {}
+
+/// Future returned by [`WsBufReadExt::read_message`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct ReadMessage<'a, R: ?Sized> {
+    reader: &'a mut R,
+}
+
+impl<R: AsyncBufRead + Unpin + ?Sized> Future for ReadMessage<'_, R> {
+    type Output = std::io::Result<Option<Vec<u8>>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        poll_message(Pin::new(&mut *self.get_mut().reader), cx)
+    }
+}
+
+/// Future returned by [`WsBufReadExt::skip_message`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct SkipMessage<'a, R: ?Sized> {
+    reader: &'a mut R,
+}
+
+impl<R: AsyncBufRead + Unpin + ?Sized> Future for SkipMessage<'_, R> {
+    type Output = std::io::Result<usize>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut reader = Pin::new(&mut *self.get_mut().reader);
+        let len = ready!(reader.as_mut().poll_fill_buf(cx))?.len();
+        reader.consume(len);
+        Poll::Ready(Ok(len))
+    }
+}
+
+/// Stream returned by [`WsBufReadExt::messages`].
+#[must_use = "streams do nothing unless polled"]
+pub struct Messages<R> {
+    reader: R,
+}
+
+impl<R: AsyncBufRead + Unpin> Stream for Messages<R> {
+    type Item = std::io::Result<Vec<u8>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        poll_message(Pin::new(&mut self.get_mut().reader), cx).map(Result::transpose)
+    }
+}
+
+fn poll_message<R: AsyncBufRead + ?Sized>(
+    mut reader: Pin<&mut R>,
+    cx: &mut Context<'_>,
+) -> Poll<std::io::Result<Option<Vec<u8>>>> {
+    let buf = ready!(reader.as_mut().poll_fill_buf(cx))?;
+    if buf.is_empty() {
+        return Poll::Ready(Ok(None));
+    }
+    let message = buf.to_vec();
+    reader.consume(message.len());
+    Poll::Ready(Ok(Some(message)))
+}
@@ -0,0 +1,59 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use web_sys::WebSocket;
+
+use crate::close::CloseSignal;
+use crate::{timer, CloseCode, WebsocketError};
+
+/// Time of the last inbound message or write, in milliseconds since the epoch.
+#[derive(Clone)]
+pub(crate) struct Activity(Rc<Cell<f64>>);
+
+impl Activity {
+    pub(crate) fn new() -> Activity {
+        Activity(Rc::new(Cell::new(js_sys::Date::now())))
+    }
+
+    pub(crate) fn touch(&self) {
+        self.0.set(js_sys::Date::now());
+    }
+
+    fn idle_for(&self) -> Duration {
+        Duration::from_millis((js_sys::Date::now() - self.0.get()).max(0.0) as u64)
+    }
+}
+
+/// Close `ws` with `code` once there was no activity for `timeout`. The reader ends with an
+/// [`IdleTimeout`](WebsocketError::IdleTimeout) error after the messages received before.
+pub(crate) fn spawn_idle_close(
+    ws: WebSocket,
+    activity: Activity,
+    timeout: Duration,
+    code: CloseCode,
+    close_signal: CloseSignal,
+) {
+    wasm_bindgen_futures::spawn_local(async move {
+        loop {
+            let idle = activity.idle_for();
+            if idle < timeout {
+                timer::sleep(timeout - idle).await;
+                continue;
+            }
+            if ws.ready_state() != WebSocket::OPEN {
+                return;
+            }
+
+            close_signal.close_locally(Some(WebsocketError::IdleTimeout(timeout)));
+            let closed = code.is_sendable()
+                && ws
+                    .close_with_code_and_reason(code.into(), "idle timeout")
+                    .is_ok();
+            if !closed {
+                let _ = ws.close();
+            }
+            return;
+        }
+    });
+}
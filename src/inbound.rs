@@ -0,0 +1,131 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use futures_channel::mpsc::UnboundedSender;
+
+use crate::{Message, WebsocketError};
+
+/// Something the reader receives, in the order the socket's events fired.
+pub(crate) enum Inbound {
+    Message(std::io::Result<Message>),
+    /// The connection closed, with an error if it didn't close cleanly.
+    End(Option<WebsocketError>),
+}
+
+type Slot = Rc<RefCell<Option<Inbound>>>;
+
+/// Hands items to the reader in order, holding back those behind a slot reserved for a message
+/// that is still being converted asynchronously, e.g. a `Blob`.
+#[derive(Clone)]
+pub(crate) struct Sequencer {
+    tx: UnboundedSender<std::io::Result<Message>>,
+    queue: Rc<RefCell<VecDeque<Slot>>>,
+}
+
+impl Sequencer {
+    pub(crate) fn new(tx: UnboundedSender<std::io::Result<Message>>) -> Sequencer {
+        Sequencer {
+            tx,
+            queue: Rc::default(),
+        }
+    }
+
+    /// Whether the reader is gone, nobody is interested in the data anymore.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    pub(crate) fn push(&self, item: Inbound) {
+        if self.queue.borrow().is_empty() {
+            self.deliver(item);
+        } else {
+            let slot = Rc::new(RefCell::new(Some(item)));
+            self.queue.borrow_mut().push_back(slot);
+        }
+    }
+
+    /// Reserves the position of an item that is only available later, see [`fill`](Self::fill).
+    pub(crate) fn reserve(&self) -> Slot {
+        let slot = Slot::default();
+        self.queue.borrow_mut().push_back(Rc::clone(&slot));
+        slot
+    }
+
+    pub(crate) fn fill(&self, slot: &Slot, item: Inbound) {
+        *slot.borrow_mut() = Some(item);
+
+        loop {
+            let item = {
+                let mut queue = self.queue.borrow_mut();
+                match queue.front().and_then(|slot| slot.borrow_mut().take()) {
+                    Some(item) => {
+                        queue.pop_front();
+                        item
+                    }
+                    None => break,
+                }
+            };
+            self.deliver(item);
+        }
+    }
+
+    fn deliver(&self, item: Inbound) {
+        // sending only fails when the reader was dropped in the meantime
+        match item {
+            Inbound::Message(message) => {
+                let _ = self.tx.unbounded_send(message);
+            }
+            Inbound::End(error) => {
+                if let Some(error) = error {
+                    let _ = self.tx.unbounded_send(Err(error.into()));
+                }
+                self.tx.close_channel();
+            }
+        }
+    }
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod tests {
+    use std::time::Duration;
+
+    use futures_util::StreamExt;
+    use js_sys::Uint8Array;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    fn message(data: &[u8]) -> Inbound {
+        Inbound::Message(Ok(Message {
+            data: Uint8Array::from(data),
+            received_at: 0.0,
+        }))
+    }
+
+    #[wasm_bindgen_test]
+    async fn end_waits_for_reserved_messages() {
+        let (tx, mut rx) = futures_channel::mpsc::unbounded();
+        let inbound = Sequencer::new(tx);
+
+        inbound.push(message(b"a"));
+        let slot = inbound.reserve();
+        inbound.push(message(b"c"));
+        let timeout = Duration::from_secs(1);
+        inbound.push(Inbound::End(Some(WebsocketError::IdleTimeout(timeout))));
+
+        let first = rx.next().await.unwrap().unwrap();
+        assert_eq!(first.data.to_vec(), b"a");
+        inbound.fill(&slot, message(b"b"));
+
+        let rest: Vec<_> = rx.collect().await;
+        assert_eq!(rest.len(), 3);
+        assert_eq!(rest[0].as_ref().unwrap().data.to_vec(), b"b");
+        assert_eq!(rest[1].as_ref().unwrap().data.to_vec(), b"c");
+        let error = rest[2].as_ref().err().unwrap();
+        assert!(matches!(
+            WebsocketError::from_io(error),
+            Some(WebsocketError::IdleTimeout(_))
+        ));
+    }
+}
@@ -1,6 +1,9 @@
 //! Implementations of [`AsyncRead`](https://docs.rs/futures/0.3.17/futures/io/trait.AsyncRead.html) and [`AsyncWrite`](https://docs.rs/futures/0.3.17/futures/io/trait.AsyncWrite.html) on top of websockets using [`web-sys`](https://github.com/rustwasm/wasm-bindgen/tree/master/crates/web-sys))
 //! # Example
 //! ```rust,no_run
+//! # use futures_util::io::{AsyncBufReadExt, AsyncWriteExt};
+//! # use websocket_async_io::WebsocketIO;
+//! # macro_rules! console_log { ($($t:tt)*) => { let _ = format!($($t)*); } }
 //! # async fn run() -> Result<(), std::io::Error> {
 //! let ws = WebsocketIO::new("localhost:8000").await?;
 //! let (mut reader, mut writer) = ws.split();
@@ -18,78 +21,338 @@
 //!
 //! # Ok(())
 //! # }
-/// ```
+//! ```
+mod addr;
+mod builder;
+mod close;
+mod conflate;
+mod copy;
+#[cfg(feature = "decompress")]
+mod decompress;
+mod error;
+mod ext;
+mod idle;
+mod inbound;
+mod probe;
+mod reconnect;
+mod sniff;
+mod testing;
+mod timer;
+mod timing;
+
+pub use builder::{set_default_config, Config, OverflowPolicy, TextFrames, WebsocketBuilder};
+pub use close::{CloseCode, CloseInfo, CloseReasonPolicy};
+pub use conflate::Conflation;
+pub use copy::copy_bidirectional;
+pub use error::WebsocketError;
+pub use ext::{Messages, ReadMessage, SkipMessage, WsBufReadExt};
+pub use probe::ProbeResult;
+pub use reconnect::{ReconnectingWebsocketIO, RetryDecision};
+pub use sniff::Sniffer;
+#[cfg(feature = "testing")]
+pub use testing::PollStats;
+pub use timing::ConnectTiming;
+
+use std::cell::{Cell, RefCell};
 use std::cmp::Ordering;
+use std::future::Future;
 use std::pin::Pin;
-use std::task::Poll;
+use std::rc::Rc;
+use std::task::{ready, Poll};
+use std::time::Duration;
 
-use futures_channel::mpsc::Receiver;
+use futures_channel::mpsc::UnboundedReceiver;
 use futures_core::stream::Stream;
 use futures_io::AsyncBufRead;
 use futures_io::AsyncRead;
 use futures_io::AsyncWrite;
 use js_sys::Uint8Array;
+use smallvec::SmallVec;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
-use web_sys::{ErrorEvent, MessageEvent, WebSocket};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{BinaryType, CloseEvent, ErrorEvent, MessageEvent, WebSocket};
+
+use crate::close::CloseSignal;
+use crate::conflate::ConflatingQueue;
+use crate::idle::Activity;
+use crate::inbound::{Inbound, Sequencer};
+use crate::testing::PollCounter;
+use crate::timing::Timestamps;
 
 pub struct WebsocketIO {
     ws: WebSocket,
     reader: WebsocketReader,
+    activity: Activity,
+    auth_protocol: Option<String>,
+    close_reason_policy: CloseReasonPolicy,
+    close_signal: CloseSignal,
+    timestamps: Timestamps,
+    write_high_water_mark: Option<usize>,
+}
+
+/// A received message, timestamped with its arrival in milliseconds since the epoch.
+pub(crate) struct Message {
+    data: Uint8Array,
+    received_at: f64,
 }
 
-struct WebsocketReader {
-    read_rx: Receiver<Uint8Array>,
-    remaining: Vec<u8>,
+/// Messages up to this size are buffered by the reader without allocating.
+const INLINE_BUFFER_SIZE: usize = 256;
+
+/// Reading half of a [`WebsocketIO`], created by [`WebsocketIO::split`].
+pub struct WebsocketReader {
+    read_rx: UnboundedReceiver<std::io::Result<Message>>,
+    remaining: SmallVec<[u8; INLINE_BUFFER_SIZE]>,
+    /// bytes received from the socket that haven't been handed out to the application yet
+    buffered: Rc<Cell<usize>>,
+    max_age: Option<Duration>,
+    stale_dropped: u64,
+    conflation: Option<Box<dyn ConflatingQueue>>,
+    /// channel item that ended draining into the conflating queue, delivered once it's empty
+    deferred: Option<Option<std::io::Result<Message>>>,
+    conflated: u64,
+    #[cfg(feature = "decompress")]
+    decompress: bool,
+    /// limits of `max_message_size` and `max_buffered_bytes`, applied again to decoded messages
+    #[cfg(feature = "decompress")]
+    decode_limits: (Option<usize>, Option<usize>),
+    polls: PollCounter,
 }
-struct WebsocketWriter {
+/// Writing half of a [`WebsocketIO`], created by [`WebsocketIO::split`].
+pub struct WebsocketWriter {
     ws: WebSocket,
+    activity: Activity,
+    close_reason_policy: CloseReasonPolicy,
+    close_signal: CloseSignal,
+    write_high_water_mark: Option<usize>,
+    /// timer waking a write blocked by the high water mark to check `bufferedAmount` again
+    drain_wait: Option<timer::Sleep>,
+    state: WriterState,
+    polls: PollCounter,
+}
+
+/// Progress of a [`WebsocketWriter`] towards being closed.
+enum WriterState {
+    Open,
+    /// The closing handshake started, waiting for the `close` event.
+    Closing,
+    Closed,
+    /// Sending failed, every later write and flush fails the same way.
+    Errored {
+        name: String,
+        message: String,
+    },
 }
 
 impl WebsocketIO {
     pub async fn new(addr: &str) -> Result<WebsocketIO, std::io::Error> {
-        WebsocketIO::new_inner(&format!("ws://{}", addr)).await
+        WebsocketIO::builder(addr).connect().await
     }
     pub async fn new_wss(addr: &str) -> Result<WebsocketIO, std::io::Error> {
-        WebsocketIO::new_inner(&format!("wss://{}", addr)).await
+        WebsocketIO::builder(addr).secure(true).connect().await
     }
 
-    async fn new_inner(url: &str) -> Result<WebsocketIO, std::io::Error> {
-        let ws =
-            WebSocket::new(url).map_err(|e| -> std::io::Error { todo!("map error: {:?}", e) })?;
+    /// Configures a connection to `addr`, given as `host[:port][/path]` without a scheme.
+    ///
+    /// IPv6 literals can be passed with or without brackets, e.g. `::1:8080` or `[::1]:8080`.
+    pub fn builder(addr: &str) -> WebsocketBuilder {
+        WebsocketBuilder::new(addr)
+    }
+
+    async fn new_inner(url: &str, config: Config) -> Result<WebsocketIO, std::io::Error> {
+        let timestamps = Timestamps::start();
+        let has_websocket =
+            js_sys::Reflect::has(&js_sys::global(), &JsValue::from_str("WebSocket"))
+                .unwrap_or(false);
+        if !has_websocket {
+            return Err(WebsocketError::Unsupported.into());
+        }
 
-        let buffer = 4;
+        let mut protocols = config.protocols.clone();
+        protocols.extend(config.auth_protocol.clone());
+        let ws = if protocols.is_empty() {
+            WebSocket::new(url)
+        } else {
+            let protocols: js_sys::Array = protocols.iter().map(JsValue::from).collect();
+            WebSocket::new_with_str_sequence(url, &protocols)
+        };
+        let ws = ws.map_err(|e| {
+            let (name, message) = error::exception(e);
+            WebsocketError::Connect { name, message }
+        })?;
+        // closes the socket unless the connection opens
+        let abandoned = CloseOnDrop(Some(ws.clone()));
 
         let (open_tx, open_rx) = futures_channel::oneshot::channel();
-        let (read_tx, read_rx) = futures_channel::mpsc::channel(buffer);
+        // received messages can't be pushed back to the server, so the channel grows as needed.
+        // `max_buffered_bytes` bounds it, dropping messages according to its policy.
+        let (read_tx, read_rx) = futures_channel::mpsc::unbounded();
+        let buffered = Rc::new(Cell::new(0));
+        let activity = Activity::new();
 
+        // binary frames arrive as `ArrayBuffer`s, which can be handed to the reader right away.
+        // `Blob`s are still accepted, but messages behind them wait for their conversion.
+        ws.set_binary_type(BinaryType::Arraybuffer);
+
+        let close_signal = CloseSignal::default();
+        let inbound = Sequencer::new(read_tx);
+        let error_tx = inbound.clone();
+        let close_tx = inbound.clone();
+        let activity_c = activity.clone();
+        let ws_c = ws.clone();
+        let close_signal_c = close_signal.clone();
+        let timestamps_c = timestamps.clone();
+        let buffered_c = Rc::clone(&buffered);
         let onmessage_callback = Closure::wrap(Box::new(move |e: MessageEvent| {
-            let mut read_tx = read_tx.clone();
-            let blob = match e.data().dyn_into::<web_sys::Blob>() {
-                Ok(blob) => blob,
-                _ => return,
+            activity_c.touch();
+            let received_at = js_sys::Date::now();
+            timestamps_c.message(received_at);
+            if inbound.is_closed() {
+                return;
+            }
+
+            enum Payload {
+                Bytes(Uint8Array),
+                Blob(web_sys::Blob),
+            }
+            let data = e.data();
+            let payload = if let Some(buffer) = data.dyn_ref::<js_sys::ArrayBuffer>() {
+                Payload::Bytes(Uint8Array::new(buffer))
+            } else if let Some(blob) = data.dyn_ref::<web_sys::Blob>() {
+                Payload::Blob(blob.clone())
+            } else {
+                match (data.as_string(), config.text_frames) {
+                    (Some(text), TextFrames::Deliver) => {
+                        Payload::Bytes(Uint8Array::from(text.as_bytes()))
+                    }
+                    (_, TextFrames::Reject) => {
+                        let error = WebsocketError::Decode("received a non-binary frame".into());
+                        inbound.push(Inbound::Message(Err(error.into())));
+                        return;
+                    }
+                    _ => return,
+                }
             };
 
-            let fr = web_sys::FileReader::new().unwrap();
-            let fr_c = fr.clone();
-            let file_reader_load_end = Closure::wrap(Box::new(move |_e: web_sys::ProgressEvent| {
-                let array = Uint8Array::new(&fr_c.result().unwrap());
-                read_tx.start_send(array).unwrap();
-            })
-                as Box<dyn FnMut(web_sys::ProgressEvent)>);
-            fr.set_onloadend(Some(file_reader_load_end.as_ref().unchecked_ref()));
-            file_reader_load_end.forget();
-
-            fr.read_as_array_buffer(&blob).expect("blob not readable");
+            let size = match &payload {
+                Payload::Bytes(data) => data.length() as usize,
+                Payload::Blob(blob) => blob.size() as usize,
+            };
+            if let Some((limit, close)) = config.max_message_size {
+                if size > limit {
+                    let error = WebsocketError::MessageTooBig { size, limit };
+                    inbound.push(Inbound::Message(Err(error.into())));
+                    if let Some(code) = close {
+                        close_signal_c.close_locally(None);
+                        let closed = code.is_sendable()
+                            && ws_c
+                                .close_with_code_and_reason(code.into(), "message too big")
+                                .is_ok();
+                        if !closed {
+                            let _ = ws_c.close();
+                        }
+                    }
+                    return;
+                }
+            }
+            if let Some((limit, policy)) = config.max_buffered {
+                if buffered_c.get() + size > limit {
+                    let error = WebsocketError::BufferFull { size, limit };
+                    match policy {
+                        OverflowPolicy::Error => inbound.push(Inbound::Message(Err(error.into()))),
+                        OverflowPolicy::Drop => {}
+                        OverflowPolicy::Close => {
+                            inbound.push(Inbound::Message(Err(error.into())));
+                            close_signal_c.close_locally(None);
+                            let _ = ws_c.close();
+                        }
+                    }
+                    return;
+                }
+            }
+            buffered_c.set(buffered_c.get() + size);
+
+            match payload {
+                Payload::Bytes(data) => {
+                    inbound.push(Inbound::Message(Ok(Message { data, received_at })));
+                }
+                Payload::Blob(blob) => {
+                    convert_blob(&inbound, blob, received_at, Rc::clone(&buffered_c));
+                }
+            }
         }) as Box<dyn Fn(MessageEvent)>);
 
-        let onerror_callback =
-            Closure::wrap(Box::new(move |_: ErrorEvent| {}) as Box<dyn FnMut(ErrorEvent)>);
+        // a failure before the connection opened fails `new`, afterwards it's reported to the
+        // reader. Browsers follow the `error` event of a failed handshake with a `close` event,
+        // which carries the details, so the error's message is only kept for that.
+        let open_tx = Rc::new(RefCell::new(Some(open_tx)));
+        let handshake_error = Rc::new(RefCell::new(None));
+
+        let open_tx_c = Rc::clone(&open_tx);
+        let handshake_error_c = Rc::clone(&handshake_error);
+        let onerror_callback = Closure::wrap(Box::new(move |e: web_sys::Event| {
+            // browsers usually fire a plain `Event` without any details
+            let message = e
+                .dyn_ref::<ErrorEvent>()
+                .map(ErrorEvent::message)
+                .filter(|message| !message.is_empty())
+                .unwrap_or_else(|| "the browser reported a websocket error".to_string());
 
-        let mut open_tx = Some(open_tx);
-        let onopen_callback =
-            Closure::wrap(Box::new(move |_| open_tx.take().unwrap().send(()).unwrap())
-                as Box<dyn FnMut(JsValue)>);
+            if open_tx_c.borrow().is_some() {
+                *handshake_error_c.borrow_mut() = Some(message);
+            } else {
+                let error = WebsocketError::Socket(message).into();
+                error_tx.push(Inbound::Message(Err(error)));
+            }
+        }) as Box<dyn FnMut(web_sys::Event)>);
+
+        let close_signal_c = close_signal.clone();
+        let open_tx_c = Rc::clone(&open_tx);
+        let timestamps_c = timestamps.clone();
+        let onclose_callback = Closure::wrap(Box::new(move |e: CloseEvent| {
+            let code = CloseCode::from(e.code());
+            if let Some(open_tx) = open_tx_c.borrow_mut().take() {
+                let error = if is_offline() {
+                    WebsocketError::Offline
+                } else {
+                    handshake_rejected(
+                        timestamps_c.elapsed(),
+                        code,
+                        &e.reason(),
+                        handshake_error.borrow_mut().take(),
+                    )
+                };
+                let _ = open_tx.send(Err(error.into()));
+            }
+            close_signal_c.set(CloseInfo {
+                code,
+                reason: e.reason(),
+                was_clean: e.was_clean(),
+            });
+            let error = match close_signal_c.take_local() {
+                Some(Some(error)) => Some(error),
+                // this side closed the connection, the code it sent isn't the server's failure
+                Some(None) if e.was_clean() => None,
+                _ => {
+                    // `NoStatus` is reported for closes initiated by `close()` without a code
+                    let clean =
+                        e.was_clean() && matches!(code, CloseCode::Normal | CloseCode::NoStatus);
+                    (!clean).then(|| WebsocketError::Closed {
+                        code,
+                        reason: e.reason(),
+                    })
+                }
+            };
+            // behind any messages still being converted
+            close_tx.push(Inbound::End(error));
+        }) as Box<dyn FnMut(CloseEvent)>);
+
+        let onopen_callback = Closure::wrap(Box::new(move |_| {
+            if let Some(open_tx) = open_tx.borrow_mut().take() {
+                let _ = open_tx.send(Ok(()));
+            }
+        }) as Box<dyn FnMut(JsValue)>);
 
         ws.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
         onmessage_callback.forget();
@@ -97,27 +360,291 @@ impl WebsocketIO {
         ws.set_onerror(Some(onerror_callback.as_ref().unchecked_ref()));
         onerror_callback.forget();
 
+        ws.set_onclose(Some(onclose_callback.as_ref().unchecked_ref()));
+        onclose_callback.forget();
+
         ws.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
         onopen_callback.forget();
 
-        let reader = WebsocketReader {
-            read_rx,
-            remaining: Vec::new(),
-        };
+        let reader = WebsocketReader::new(read_rx, buffered, &config);
 
-        open_rx.await.unwrap();
+        let mut open_rx = open_rx;
+        let mut deadline = config
+            .connect_timeout
+            .map(|timeout| (timeout, timer::sleep(timeout)));
+        let opened: std::io::Result<()> = std::future::poll_fn(|cx| {
+            if let Poll::Ready(result) = Pin::new(&mut open_rx).poll(cx) {
+                // the sender lives in the forgotten callbacks, so this is never cancelled in practice
+                return Poll::Ready(
+                    result.unwrap_or_else(|_| Err(WebsocketError::NotConnected.into())),
+                );
+            }
+            match &mut deadline {
+                Some((timeout, sleep)) => Pin::new(sleep)
+                    .poll(cx)
+                    .map(|()| Err(WebsocketError::ConnectTimeout(*timeout).into())),
+                None => Poll::Pending,
+            }
+        })
+        .await;
+        opened?;
+        abandoned.disarm();
+
+        timestamps.opened();
+        activity.touch();
+        if let Some((timeout, code)) = config.idle_timeout {
+            idle::spawn_idle_close(
+                ws.clone(),
+                activity.clone(),
+                timeout,
+                code,
+                close_signal.clone(),
+            );
+        }
 
-        let ws_io = WebsocketIO { ws, reader };
+        let ws_io = WebsocketIO {
+            ws,
+            reader,
+            activity,
+            auth_protocol: config.auth_protocol,
+            close_reason_policy: config.close_reason_policy,
+            close_signal,
+            timestamps,
+            write_high_water_mark: config.write_high_water_mark,
+        };
         Ok(ws_io)
     }
 
-    pub fn split(self) -> (impl AsyncBufRead, impl AsyncWrite) {
-        let WebsocketIO { ws, reader } = self;
-        (reader, WebsocketWriter { ws })
+    /// The subprotocol selected by the server, if any.
+    ///
+    /// A token passed to [`auth_via_protocol`](WebsocketBuilder::auth_via_protocol) is never
+    /// returned, even if the server echoed it back.
+    pub fn protocol(&self) -> Option<String> {
+        let protocol = self.ws.protocol();
+        if protocol.is_empty() || Some(&protocol) == self.auth_protocol.as_ref() {
+            return None;
+        }
+        Some(protocol)
+    }
+
+    /// Resolves once the connection closed, with the code, reason and whether the closing
+    /// handshake completed.
+    ///
+    /// The future doesn't borrow the connection, so it can be awaited next to reading and
+    /// writing. It is also available from [`WebsocketWriter::closed`] after splitting.
+    pub fn closed(&self) -> impl Future<Output = CloseInfo> + 'static {
+        self.close_signal.wait()
+    }
+
+    /// How long establishing the connection took.
+    pub fn connect_timing(&self) -> ConnectTiming {
+        self.timestamps.timing()
+    }
+
+    /// Starts closing the connection with a close code and reason, see
+    /// [`WebsocketWriter::close_with`].
+    pub fn close_with(&mut self, code: impl Into<CloseCode>, reason: &str) -> std::io::Result<()> {
+        close::close_with(
+            &self.ws,
+            &self.close_signal,
+            self.close_reason_policy,
+            code.into(),
+            reason,
+        )
+    }
+
+    pub fn split(self) -> (WebsocketReader, WebsocketWriter) {
+        let WebsocketIO {
+            ws,
+            reader,
+            activity,
+            close_reason_policy,
+            close_signal,
+            write_high_water_mark,
+            ..
+        } = self;
+        let writer = WebsocketWriter {
+            ws,
+            activity,
+            close_reason_policy,
+            close_signal,
+            write_high_water_mark,
+            drain_wait: None,
+            state: WriterState::Open,
+            polls: PollCounter::default(),
+        };
+        (reader, writer)
     }
 }
 
 impl WebsocketReader {
+    fn new(
+        read_rx: UnboundedReceiver<std::io::Result<Message>>,
+        buffered: Rc<Cell<usize>>,
+        config: &Config,
+    ) -> WebsocketReader {
+        WebsocketReader {
+            read_rx,
+            remaining: SmallVec::new(),
+            buffered,
+            max_age: config.max_age,
+            stale_dropped: 0,
+            conflation: config
+                .conflation
+                .as_ref()
+                .map(|conflation| conflation.queue()),
+            deferred: None,
+            conflated: 0,
+            #[cfg(feature = "decompress")]
+            decompress: config.decompress,
+            #[cfg(feature = "decompress")]
+            decode_limits: (
+                config.max_message_size.map(|(limit, _)| limit),
+                config.max_buffered.map(|(limit, _)| limit),
+            ),
+            polls: PollCounter::default(),
+        }
+    }
+
+    /// Wraps the reader to inspect its first `n` bytes, e.g. to choose between protocols.
+    ///
+    /// ```rust,no_run
+    /// # use websocket_async_io::WebsocketIO;
+    /// # async fn run() -> Result<(), std::io::Error> {
+    /// let (reader, _writer) = WebsocketIO::new("localhost:8000").await?.split();
+    ///
+    /// let mut sniffer = reader.into_sniffer(4);
+    /// let is_http = sniffer.peek().await? == b"HTTP";
+    /// // still starts with the four sniffed bytes
+    /// let reader = sniffer.into_reader();
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn into_sniffer(self, n: usize) -> Sniffer {
+        Sniffer::new(self, n)
+    }
+
+    /// The number of messages discarded because they were older than
+    /// [`max_message_age`](WebsocketBuilder::max_message_age) when the application got to them.
+    pub fn stale_dropped(&self) -> u64 {
+        self.stale_dropped
+    }
+
+    /// The number of messages replaced by a newer message with the same key, see
+    /// [`conflate`](WebsocketBuilder::conflate).
+    pub fn conflated(&self) -> u64 {
+        self.conflated
+    }
+
+    /// Poll statistics of this reader.
+    #[cfg(feature = "testing")]
+    pub fn poll_stats(&self) -> PollStats {
+        self.polls.stats()
+    }
+
+    /// Marks `amount` bytes as no longer held by the reader.
+    fn release(&self, amount: usize) {
+        self.buffered
+            .set(self.buffered.get().saturating_sub(amount));
+    }
+
+    fn poll_message(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<std::io::Result<Uint8Array>>> {
+        loop {
+            let message = match self.poll_queued(cx) {
+                Poll::Ready(Some(Ok(message))) => message,
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            };
+
+            if let Some(max_age) = self.max_age {
+                let age = js_sys::Date::now() - message.received_at;
+                if age > max_age.as_millis() as f64 {
+                    self.stale_dropped += 1;
+                    self.release(message.data.length() as usize);
+                    continue;
+                }
+            }
+
+            #[cfg(feature = "decompress")]
+            if self.decompress {
+                return Poll::Ready(Some(self.decompress(message.data)));
+            }
+
+            return Poll::Ready(Some(Ok(message.data)));
+        }
+    }
+
+    #[cfg(feature = "decompress")]
+    fn decompress(&self, data: Uint8Array) -> std::io::Result<Uint8Array> {
+        let compressed = data.to_vec();
+        // the data was accounted for with its compressed size
+        self.release(compressed.len());
+
+        let (message_limit, buffer_limit) = self.decode_limits;
+        let space = buffer_limit.map(|limit| limit.saturating_sub(self.buffered.get()));
+        let limit = message_limit.into_iter().chain(space).min();
+
+        let decoded = decompress::sniff_and_decompress(&compressed, limit.unwrap_or(usize::MAX))
+            .map_err(|e| WebsocketError::Decode(e.to_string()))?;
+        let data = match decoded {
+            // decoding stopped at the limit, so `size` is only a lower bound
+            Some(decoded) if limit.is_some_and(|limit| decoded.len() > limit) => {
+                let size = decoded.len();
+                let error = match (message_limit, buffer_limit) {
+                    (Some(limit), _) if size > limit => {
+                        WebsocketError::MessageTooBig { size, limit }
+                    }
+                    (_, Some(limit)) => WebsocketError::BufferFull { size, limit },
+                    _ => unreachable!("one of the limits was exceeded"),
+                };
+                return Err(error.into());
+            }
+            Some(decoded) => Uint8Array::from(decoded.as_slice()),
+            None => data,
+        };
+        self.buffered
+            .set(self.buffered.get() + data.length() as usize);
+        Ok(data)
+    }
+
+    /// Polls the next message from the channel, or from the conflating queue if enabled.
+    fn poll_queued(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<std::io::Result<Message>>> {
+        let conflation = match &mut self.conflation {
+            Some(conflation) => conflation,
+            None => return Pin::new(&mut self.read_rx).poll_next(cx),
+        };
+
+        while self.deferred.is_none() {
+            match Pin::new(&mut self.read_rx).poll_next(cx) {
+                Poll::Ready(Some(Ok(message))) => {
+                    if let Some(replaced) = conflation.push(message) {
+                        self.conflated += 1;
+                        let buffered = self.buffered.get();
+                        let size = replaced.data.length() as usize;
+                        self.buffered.set(buffered.saturating_sub(size));
+                    }
+                }
+                Poll::Ready(item) => self.deferred = Some(item),
+                Poll::Pending => break,
+            }
+        }
+
+        if let Some(message) = conflation.pop() {
+            return Poll::Ready(Some(Ok(message)));
+        }
+        match self.deferred.take() {
+            Some(item) => Poll::Ready(item),
+            None => Poll::Pending,
+        }
+    }
+
     fn write_remaining(&mut self, buf: &mut [u8]) -> usize {
         match self.remaining.len().cmp(&buf.len()) {
             Ordering::Less => {
@@ -141,20 +668,37 @@ impl WebsocketReader {
     }
 }
 
-impl AsyncRead for WebsocketReader {
-    fn poll_read(
+impl Drop for WebsocketReader {
+    fn drop(&mut self) {
+        self.read_rx.close();
+    }
+}
+
+impl WebsocketReader {
+    fn poll_read_inner(
         mut self: std::pin::Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
         buf: &mut [u8],
     ) -> Poll<std::io::Result<usize>> {
+        // don't take a message out of the channel that can't be handed out
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
         if !self.remaining.is_empty() {
-            return Poll::Ready(Ok(self.write_remaining(buf)));
+            let read = self.write_remaining(buf);
+            self.release(read);
+            return Poll::Ready(Ok(read));
         }
 
-        let array = match Pin::new(&mut self.read_rx).poll_next(cx) {
-            Poll::Ready(Some(item)) => item,
-            Poll::Ready(None) => return Poll::Pending,
-            Poll::Pending => return Poll::Pending,
+        let array = loop {
+            match self.poll_message(cx) {
+                // an empty message has no bytes to read, returning 0 for it would signal EOF
+                Poll::Ready(Some(Ok(item))) if item.length() == 0 => continue,
+                Poll::Ready(Some(Ok(item))) => break item,
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Err(e)),
+                Poll::Ready(None) => return Poll::Ready(Ok(0)),
+                Poll::Pending => return Poll::Pending,
+            }
         };
 
         let array_length = array.length() as usize;
@@ -175,12 +719,12 @@ impl AsyncRead for WebsocketReader {
                 self.write_remaining(buf)
             }
         };
+        self.release(read);
 
         Poll::Ready(Ok(read))
     }
-}
-impl AsyncBufRead for WebsocketReader {
-    fn poll_fill_buf(
+
+    fn poll_fill_buf_inner(
         mut self: std::pin::Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> Poll<futures_io::Result<&[u8]>> {
@@ -188,21 +732,47 @@ impl AsyncBufRead for WebsocketReader {
             return Poll::Ready(Ok(self.get_mut().remaining.as_slice()));
         }
 
-        let array = match Pin::new(&mut self.read_rx).poll_next(cx) {
-            Poll::Ready(Some(item)) => item,
-            Poll::Ready(None) => return Poll::Pending,
-            Poll::Pending => return Poll::Pending,
+        let array = loop {
+            match self.poll_message(cx) {
+                // returning an empty buffer for an empty message would signal EOF, and returning
+                // `Pending` without polling the channel again would never wake the task
+                Poll::Ready(Some(Ok(item))) if item.length() == 0 => continue,
+                Poll::Ready(Some(Ok(item))) => break item,
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Err(e)),
+                Poll::Ready(None) => return Poll::Ready(Ok(&[])),
+                Poll::Pending => return Poll::Pending,
+            }
         };
 
-        self.remaining.extend(&array.to_vec());
+        let array_length = array.length() as usize;
+        self.remaining.resize(array_length, 0);
+        array.copy_to(self.as_mut().remaining.as_mut_slice());
 
-        if self.remaining.len() == 0 {
-            return Poll::Pending;
-        }
         Poll::Ready(Ok(self.get_mut().remaining.as_slice()))
     }
+}
+
+impl AsyncRead for WebsocketReader {
+    fn poll_read(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let polls = self.polls.clone();
+        polls.track(cx, |cx| self.poll_read_inner(cx, buf))
+    }
+}
+impl AsyncBufRead for WebsocketReader {
+    fn poll_fill_buf(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<futures_io::Result<&[u8]>> {
+        let polls = self.polls.clone();
+        polls.track(cx, |cx| self.poll_fill_buf_inner(cx))
+    }
 
     fn consume(mut self: std::pin::Pin<&mut Self>, amt: usize) {
+        self.release(amt);
         if self.remaining.len() == amt {
             self.remaining.clear();
             return;
@@ -211,29 +781,486 @@ impl AsyncBufRead for WebsocketReader {
     }
 }
 
-impl AsyncWrite for WebsocketWriter {
-    fn poll_write(
-        self: std::pin::Pin<&mut Self>,
-        _: &mut std::task::Context<'_>,
+/// Reads `blob` in the background, holding back the messages received after it until then.
+///
+/// `buffered` already accounts for the blob, its size is released again if reading fails.
+fn convert_blob(
+    inbound: &Sequencer,
+    blob: web_sys::Blob,
+    received_at: f64,
+    buffered: Rc<Cell<usize>>,
+) {
+    let slot = inbound.reserve();
+    let inbound = inbound.clone();
+    wasm_bindgen_futures::spawn_local(async move {
+        let buffer = JsFuture::from(blob.array_buffer()).await;
+        let message = match buffer {
+            Ok(buffer) => Ok(Message {
+                data: Uint8Array::new(&buffer),
+                received_at,
+            }),
+            Err(e) => {
+                let size = blob.size() as usize;
+                buffered.set(buffered.get().saturating_sub(size));
+                Err(WebsocketError::js(e).into())
+            }
+        };
+        inbound.fill(&slot, Inbound::Message(message));
+    });
+}
+
+/// Closes the socket of a connection attempt that didn't succeed. Its callbacks are never
+/// released, so the socket would open later and stay open otherwise.
+struct CloseOnDrop(Option<WebSocket>);
+
+impl CloseOnDrop {
+    fn disarm(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for CloseOnDrop {
+    fn drop(&mut self) {
+        if let Some(ws) = self.0.take() {
+            let _ = ws.close();
+        }
+    }
+}
+
+/// Whether the browser knows it has no network connection (`navigator.onLine`).
+fn is_offline() -> bool {
+    js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str("navigator"))
+        .and_then(|navigator| js_sys::Reflect::get(&navigator, &JsValue::from_str("onLine")))
+        .ok()
+        .and_then(|online| online.as_bool())
+        == Some(false)
+}
+
+/// Describes a connection that closed before it opened.
+///
+/// Browsers hide the HTTP response of a failed upgrade, so this can't tell an unreachable server
+/// from one rejecting the upgrade, e.g. an auth proxy answering with 401. The time it took and
+/// the close event's details are the only hints.
+fn handshake_rejected(
+    elapsed: Duration,
+    code: CloseCode,
+    reason: &str,
+    error: Option<String>,
+) -> WebsocketError {
+    let mut message = format!(
+        "connection closed with code {} after {:?} without completing the handshake",
+        u16::from(code),
+        elapsed
+    );
+    if !reason.is_empty() {
+        message.push_str(&format!(" ({})", reason));
+    }
+    if let Some(error) = error {
+        message.push_str(&format!(": {}", error));
+    }
+    message.push_str(
+        "; the server is unreachable or rejected the upgrade, \
+         the browser's network panel shows the HTTP status",
+    );
+    WebsocketError::HandshakeRejected(message)
+}
+
+/// How often [`WebsocketWriter::drained`] checks the browser's send buffer.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+impl WebsocketWriter {
+    /// Resolves once the browser has handed all written data to the network
+    /// (`bufferedAmount` is zero).
+    ///
+    /// Unlike [`poll_flush`](AsyncWrite::poll_flush), this actually waits for the
+    /// browser's send buffer, which makes it suitable for "safe to leave the page" checks.
+    /// Fails with [`NotConnected`](std::io::ErrorKind::NotConnected) if the connection closes
+    /// while data is still unsent.
+    pub fn drained(&self) -> impl Future<Output = std::io::Result<()>> {
+        let ws = self.ws.clone();
+        async move {
+            while ws.buffered_amount() > 0 {
+                if ws.ready_state() == WebSocket::CLOSED {
+                    return Err(WebsocketError::NotConnected.into());
+                }
+                timer::sleep(DRAIN_POLL_INTERVAL).await;
+            }
+            Ok(())
+        }
+    }
+
+    /// Resolves once the connection closed, see [`WebsocketIO::closed`].
+    pub fn closed(&self) -> impl Future<Output = CloseInfo> + 'static {
+        self.close_signal.wait()
+    }
+
+    /// Sends every item of `stream` as its own websocket message, returning the number of bytes
+    /// sent once the stream ends. Empty items are skipped, like empty writes.
+    ///
+    /// The next item is only pulled from the stream once the previous one was accepted by the
+    /// writer. Dropping the returned future stops forwarding without sending partial messages.
+    pub async fn send_stream<S>(&mut self, stream: S) -> std::io::Result<u64>
+    where
+        S: Stream,
+        S::Item: AsRef<[u8]>,
+    {
+        let mut stream = Box::pin(stream);
+        let mut total = 0;
+
+        while let Some(item) = std::future::poll_fn(|cx| stream.as_mut().poll_next(cx)).await {
+            let item = item.as_ref();
+            let mut written = 0;
+            loop {
+                let buf = &item[written..];
+                let n = std::future::poll_fn(|cx| Pin::new(&mut *self).poll_write(cx, buf)).await?;
+                if n == 0 && !buf.is_empty() {
+                    return Err(std::io::ErrorKind::WriteZero.into());
+                }
+                written += n;
+                if written == item.len() {
+                    break;
+                }
+            }
+            total += item.len() as u64;
+        }
+
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_flush(cx)).await?;
+        Ok(total)
+    }
+
+    /// Starts closing the connection with a close code and reason.
+    ///
+    /// Browsers only allow sending [`CloseCode::Normal`] as well as library (3000-3999) and
+    /// application (4000-4999) codes, other codes fail with
+    /// [`InvalidInput`](std::io::ErrorKind::InvalidInput).
+    /// Reasons longer than 123 bytes are handled according to the
+    /// [`close_reason_policy`](WebsocketBuilder::close_reason_policy).
+    ///
+    /// ```rust,no_run
+    /// # fn run(writer: &mut websocket_async_io::WebsocketWriter) -> std::io::Result<()> {
+    /// // application defined code, e.g. "unauthorized" in graphql-ws
+    /// writer.close_with(4401, "Unauthorized")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn close_with(&mut self, code: impl Into<CloseCode>, reason: &str) -> std::io::Result<()> {
+        close::close_with(
+            &self.ws,
+            &self.close_signal,
+            self.close_reason_policy,
+            code.into(),
+            reason,
+        )?;
+        if let WriterState::Open = self.state {
+            self.state = WriterState::Closing;
+        }
+        Ok(())
+    }
+}
+
+impl WebsocketWriter {
+    fn errored(&self) -> Option<std::io::Error> {
+        match &self.state {
+            WriterState::Errored { name, message } => Some(
+                WebsocketError::Send {
+                    name: name.clone(),
+                    message: message.clone(),
+                }
+                .into(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Poll statistics of this writer.
+    #[cfg(feature = "testing")]
+    pub fn poll_stats(&self) -> PollStats {
+        self.polls.stats()
+    }
+
+    fn poll_write_inner(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
         buf: &[u8],
     ) -> Poll<std::io::Result<usize>> {
-        self.ws.send_with_u8_array(buf).unwrap();
+        if let Some(error) = self.errored() {
+            return Poll::Ready(Err(error));
+        }
+        // browsers silently discard messages sent while closing, report them as failed instead,
+        // also when the server closed the connection
+        let state = self.ws.ready_state();
+        let closing = state == WebSocket::CLOSING || state == WebSocket::CLOSED;
+        if closing || !matches!(self.state, WriterState::Open) {
+            return Poll::Ready(Err(WebsocketError::WriteClosed.into()));
+        }
+        // an empty message would reach the server as a frame of its own
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        if let Some(limit) = self.write_high_water_mark {
+            // browsers don't notify when `bufferedAmount` decreases, so check it periodically
+            while self.ws.buffered_amount() as usize >= limit {
+                let wait = self
+                    .drain_wait
+                    .get_or_insert_with(|| timer::sleep(DRAIN_POLL_INTERVAL));
+                if Pin::new(wait).poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                self.drain_wait = None;
+            }
+        }
+        if let Err(e) = self.ws.send_with_u8_array(buf) {
+            let (name, message) = error::exception(e);
+            self.state = WriterState::Errored {
+                name: name.clone(),
+                message: message.clone(),
+            };
+            return Poll::Ready(Err(WebsocketError::Send { name, message }.into()));
+        }
+        self.activity.touch();
 
         Poll::Ready(Ok(buf.len()))
     }
 
-    fn poll_flush(
+    fn poll_flush_inner(
         self: std::pin::Pin<&mut Self>,
         _: &mut std::task::Context<'_>,
     ) -> Poll<std::io::Result<()>> {
+        // every write is handed to the browser right away, there is nothing left to flush
+        match self.errored() {
+            Some(error) => Poll::Ready(Err(error)),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_close_inner(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.state {
+            WriterState::Closed => return Poll::Ready(Ok(())),
+            WriterState::Closing => {}
+            // a failed writer still closes the connection to release it
+            WriterState::Open | WriterState::Errored { .. } => {
+                // the server may have started closing already
+                let state = self.ws.ready_state();
+                if state == WebSocket::CONNECTING || state == WebSocket::OPEN {
+                    self.close_signal.close_locally(None);
+                    self.ws
+                        .close()
+                        .map_err(|e| std::io::Error::from(WebsocketError::js(e)))?;
+                }
+                self.state = WriterState::Closing;
+            }
+        }
+
+        ready!(self.close_signal.poll(cx));
+        self.state = WriterState::Closed;
         Poll::Ready(Ok(()))
     }
+}
 
+impl AsyncWrite for WebsocketWriter {
+    fn poll_write(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let polls = self.polls.clone();
+        polls.track(cx, |cx| self.poll_write_inner(cx, buf))
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let polls = self.polls.clone();
+        polls.track(cx, |cx| self.poll_flush_inner(cx))
+    }
+
+    /// Starts the closing handshake and resolves once the browser reports the connection as
+    /// closed.
     fn poll_close(
         self: std::pin::Pin<&mut Self>,
-        _: &mut std::task::Context<'_>,
+        cx: &mut std::task::Context<'_>,
     ) -> Poll<std::io::Result<()>> {
-        self.ws.close().unwrap();
-        Poll::Ready(Ok(()))
+        let polls = self.polls.clone();
+        polls.track(cx, |cx| self.poll_close_inner(cx))
+    }
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod tests {
+    use futures_channel::mpsc::UnboundedSender;
+    use futures_util::io::{AsyncBufReadExt, AsyncReadExt};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    fn reader() -> (UnboundedSender<std::io::Result<Message>>, WebsocketReader) {
+        reader_with(&Config::default())
+    }
+
+    fn reader_with(
+        config: &Config,
+    ) -> (UnboundedSender<std::io::Result<Message>>, WebsocketReader) {
+        let (tx, rx) = futures_channel::mpsc::unbounded();
+        let reader = WebsocketReader::new(rx, Rc::default(), config);
+        (tx, reader)
+    }
+
+    fn message(data: &[u8]) -> std::io::Result<Message> {
+        Ok(Message {
+            data: Uint8Array::from(data),
+            received_at: js_sys::Date::now(),
+        })
+    }
+
+    fn blob(data: &[u8]) -> web_sys::Blob {
+        let parts = js_sys::Array::of1(&Uint8Array::from(data));
+        web_sys::Blob::new_with_u8_array_sequence(&parts).unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    async fn blobs_keep_their_position() {
+        let (tx, rx) = futures_channel::mpsc::unbounded();
+        let mut reader = WebsocketReader::new(rx, Rc::default(), &Config::default());
+        let inbound = Sequencer::new(tx);
+
+        convert_blob(&inbound, blob(b"ab"), 0.0, Rc::default());
+        inbound.push(Inbound::Message(message(b"cd")));
+        inbound.push(Inbound::End(None));
+
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).await.unwrap();
+        assert_eq!(read, b"abcd");
+    }
+
+    #[wasm_bindgen_test]
+    async fn dropping_reader_during_blob_conversion() {
+        let (tx, reader) = reader();
+        let buffered = Rc::clone(&reader.buffered);
+        let inbound = Sequencer::new(tx);
+
+        buffered.set(2);
+        convert_blob(&inbound, blob(b"ab"), 0.0, Rc::clone(&buffered));
+        inbound.push(Inbound::Message(message(b"cd")));
+        drop(reader);
+        assert!(inbound.is_closed());
+
+        // let the conversion finish, delivering into the closed channel must not panic
+        timer::sleep(Duration::from_millis(50)).await;
+        assert_eq!(
+            Rc::strong_count(&buffered),
+            1,
+            "the conversion is still running"
+        );
+        inbound.push(Inbound::Message(message(b"ef")));
+        inbound.push(Inbound::End(None));
+    }
+
+    #[wasm_bindgen_test]
+    async fn empty_buffer_leaves_message_queued() {
+        let (tx, mut reader) = reader();
+        tx.unbounded_send(message(b"abc")).unwrap();
+
+        assert_eq!(reader.read(&mut []).await.unwrap(), 0);
+        let mut buf = [0; 8];
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 3);
+        assert_eq!(&buf[..3], b"abc");
+    }
+
+    #[wasm_bindgen_test]
+    async fn empty_messages_are_skipped() {
+        let (tx, mut reader) = reader();
+        tx.unbounded_send(message(b"")).unwrap();
+        tx.unbounded_send(message(b"abc")).unwrap();
+        tx.unbounded_send(message(b"")).unwrap();
+        tx.unbounded_send(message(b"de")).unwrap();
+
+        let mut buf = [0; 8];
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 3);
+        assert_eq!(reader.fill_buf().await.unwrap(), b"de");
+    }
+
+    #[wasm_bindgen_test]
+    async fn eof_once_channel_ends() {
+        let (tx, mut reader) = reader();
+        tx.unbounded_send(message(b"abc")).unwrap();
+        tx.unbounded_send(message(b"")).unwrap();
+        drop(tx);
+
+        let mut buf = [0; 2];
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 2);
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 1);
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 0);
+        assert_eq!(reader.fill_buf().await.unwrap(), b"");
+    }
+
+    #[wasm_bindgen_test]
+    async fn conflation_keeps_newest_message_per_key() {
+        // keyed by the first byte, `x` messages have no key
+        let config = Config {
+            conflation: Some(Conflation::new(|message: &[u8]| {
+                message.first().copied().filter(|&key| key != b'x')
+            })),
+            ..Config::default()
+        };
+        let (tx, mut reader) = reader_with(&config);
+        for data in [&b"a1"[..], b"b1", b"x1", b"a2", b"x2", b"a3"] {
+            tx.unbounded_send(message(data)).unwrap();
+        }
+        let first = reader.read_message().await.unwrap();
+        assert_eq!(first.as_deref(), Some(&b"a3"[..]));
+
+        // `b1` is still queued, `a3` was read already
+        for data in [&b"b2"[..], b"a4"] {
+            tx.unbounded_send(message(data)).unwrap();
+        }
+        drop(tx);
+
+        let mut read = Vec::new();
+        while let Some(message) = reader.read_message().await.unwrap() {
+            read.push(message);
+        }
+        assert_eq!(read, [&b"b2"[..], b"x1", b"x2", b"a4"]);
+        assert_eq!(reader.conflated(), 3);
+    }
+
+    #[wasm_bindgen_test]
+    async fn sniffed_bytes_are_read_again() {
+        let (tx, reader) = reader();
+        let buffered = Rc::clone(&reader.buffered);
+        buffered.set(7);
+        tx.unbounded_send(message(b"abc")).unwrap();
+        tx.unbounded_send(message(b"defg")).unwrap();
+        drop(tx);
+
+        // across the first message and into the second one
+        let mut sniffer = reader.into_sniffer(5);
+        assert_eq!(sniffer.peek().await.unwrap(), b"abcde");
+        assert_eq!(sniffer.peek().await.unwrap(), b"abcde");
+        assert_eq!(buffered.get(), 2);
+
+        let mut reader = sniffer.into_reader();
+        assert_eq!(buffered.get(), 7);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).await.unwrap();
+        assert_eq!(read, b"abcdefg");
+        assert_eq!(buffered.get(), 0);
+    }
+
+    #[wasm_bindgen_test]
+    async fn sniffing_stops_at_eof() {
+        let (tx, reader) = reader();
+        tx.unbounded_send(message(b"ab")).unwrap();
+        drop(tx);
+
+        let mut sniffer = reader.into_sniffer(4);
+        assert_eq!(sniffer.peek().await.unwrap(), b"ab");
+
+        let mut read = Vec::new();
+        sniffer.into_reader().read_to_end(&mut read).await.unwrap();
+        assert_eq!(read, b"ab");
     }
 }
@@ -0,0 +1,87 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::Poll;
+use std::time::Duration;
+
+use crate::{timer, CloseCode, WebsocketError, WebsocketIO, WsBufReadExt};
+
+/// Outcome of [`WebsocketIO::probe`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProbeResult {
+    /// The server's first message was the expected greeting.
+    Ok,
+    /// The connection opened, but the server sent something else first, or closed the
+    /// connection before sending anything.
+    WrongProtocol,
+    /// Connecting and waiting for the greeting took longer than the timeout.
+    Timeout,
+    /// The server couldn't be reached or rejected the opening handshake.
+    Refused,
+}
+
+impl WebsocketIO {
+    /// Checks that the server at `addr` speaks the expected protocol, without handing out the
+    /// connection, e.g. for pre-flight checks and diagnostics screens.
+    ///
+    /// Connects, waits for the first message and passes it to `expected_greeting`. `timeout`
+    /// bounds connecting and waiting together. Errors saying nothing about the server, like an
+    /// invalid address, are returned instead of being classified. The connection is closed
+    /// before returning.
+    ///
+    /// ```rust,no_run
+    /// # use std::time::Duration;
+    /// # use websocket_async_io::{ProbeResult, WebsocketIO};
+    /// # async fn run() -> Result<(), std::io::Error> {
+    /// let greeting = |message: &[u8]| message.starts_with(b"HELLO v2");
+    /// match WebsocketIO::probe("localhost:8000", greeting, Duration::from_secs(5)).await? {
+    ///     ProbeResult::Ok => {}
+    ///     ProbeResult::WrongProtocol => { /* another service is running there */ }
+    ///     ProbeResult::Timeout | ProbeResult::Refused => { /* the server is down */ }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn probe(
+        addr: &str,
+        expected_greeting: impl Fn(&[u8]) -> bool,
+        timeout: Duration,
+    ) -> std::io::Result<ProbeResult> {
+        let started = js_sys::Date::now();
+        let connected = WebsocketIO::builder(addr)
+            .connect_timeout(timeout)
+            .connect()
+            .await;
+        let mut ws = match connected {
+            Ok(ws) => ws,
+            Err(e) => {
+                return match WebsocketError::from_io(&e) {
+                    Some(WebsocketError::ConnectTimeout(_)) => Ok(ProbeResult::Timeout),
+                    Some(WebsocketError::HandshakeRejected(_) | WebsocketError::Offline) => {
+                        Ok(ProbeResult::Refused)
+                    }
+                    _ => Err(e),
+                }
+            }
+        };
+
+        let elapsed = Duration::from_millis((js_sys::Date::now() - started).max(0.0) as u64);
+        let mut deadline = timer::sleep(timeout.saturating_sub(elapsed));
+        let mut greeting = ws.reader.read_message();
+        let result = std::future::poll_fn(|cx| {
+            if let Poll::Ready(greeting) = Pin::new(&mut greeting).poll(cx) {
+                return Poll::Ready(match greeting {
+                    Ok(Some(greeting)) if expected_greeting(&greeting) => ProbeResult::Ok,
+                    // a clean close ends the reader, any other close is an error
+                    _ => ProbeResult::WrongProtocol,
+                });
+            }
+            Pin::new(&mut deadline)
+                .poll(cx)
+                .map(|()| ProbeResult::Timeout)
+        })
+        .await;
+
+        let _ = ws.close_with(CloseCode::Normal, "");
+        Ok(result)
+    }
+}
@@ -0,0 +1,409 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll, Wake, Waker};
+use std::time::Duration;
+
+use futures_io::{AsyncRead, AsyncWrite};
+
+use crate::timer::{self, Sleep};
+use crate::{
+    CloseCode, WebsocketBuilder, WebsocketError, WebsocketIO, WebsocketReader, WebsocketWriter,
+};
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// A connection that transparently reconnects with exponential backoff when the socket drops.
+///
+/// Reads and writes wait while reconnecting. Messages sent by the server while disconnected and
+/// the unread rest of a message are lost, so this suits protocols where every message stands on
+/// its own, like live dashboards. Writes that failed because the connection dropped are retried
+/// on the new connection.
+///
+/// Connecting fails permanently for errors that retrying can't fix, like a malformed address,
+/// which [`retry_with`](Self::retry_with) can change.
+/// After [`poll_close`](AsyncWrite::poll_close) it stops reconnecting and reads return EOF.
+///
+/// Connections closed by [`idle_timeout`](WebsocketBuilder::idle_timeout) aren't reopened right
+/// away. Reads wait, and the next write reconnects before it's sent, so writing always works
+/// without keeping an unused socket open.
+///
+/// Reading and writing may happen from different tasks, e.g. after
+/// [`AsyncReadExt::split`](https://docs.rs/futures/0.3/futures/io/trait.AsyncReadExt.html#method.split),
+/// all of them are woken when the connection is lost or reestablished.
+///
+/// ```rust,no_run
+/// # use futures_util::io::{AsyncReadExt, AsyncWriteExt};
+/// # use std::time::Duration;
+/// # use websocket_async_io::{ReconnectingWebsocketIO, WebsocketIO};
+/// # async fn run() -> Result<(), std::io::Error> {
+/// let builder = WebsocketIO::builder("localhost:8000");
+/// let mut ws = ReconnectingWebsocketIO::new(builder)
+///     .backoff(Duration::from_millis(250), Duration::from_secs(10));
+///
+/// ws.write_all(b"subscribe").await?;
+/// let mut buf = [0; 1024];
+/// let read = ws.read(&mut buf).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ReconnectingWebsocketIO {
+    builder: WebsocketBuilder,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    /// delay before the next connection attempt
+    backoff: Duration,
+    connections: u64,
+    /// what to do after a connection attempt failed or the connection dropped
+    retry: Box<dyn Fn(&std::io::Error) -> RetryDecision>,
+    state: State,
+    waiters: Arc<Waiters>,
+    /// wakes all `waiters`, passed to the reconnect timer and connection attempts
+    waker: Waker,
+}
+
+enum State {
+    Connecting(Pin<Box<dyn Future<Output = std::io::Result<WebsocketIO>>>>),
+    Connected(Box<(WebsocketReader, WebsocketWriter)>),
+    Waiting(Sleep),
+    /// closed for being idle, the next write reconnects
+    Idle,
+    Closed,
+}
+
+impl ReconnectingWebsocketIO {
+    /// Connects with the options of `builder`, reconnecting with them whenever the socket drops.
+    pub fn new(builder: WebsocketBuilder) -> ReconnectingWebsocketIO {
+        let connecting = Box::pin(builder.clone().connect());
+        let waiters = Arc::new(Waiters::default());
+        ReconnectingWebsocketIO {
+            builder,
+            initial_backoff: INITIAL_BACKOFF,
+            max_backoff: MAX_BACKOFF,
+            backoff: INITIAL_BACKOFF,
+            connections: 0,
+            retry: Box::new(RetryDecision::default_for),
+            state: State::Connecting(connecting),
+            waker: Waker::from(Arc::clone(&waiters)),
+            waiters,
+        }
+    }
+
+    /// Wait `initial` before the first reconnection attempt, doubling the delay after every
+    /// failed attempt up to `max`. Defaults to 100ms and 30s.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self.backoff = initial;
+        self
+    }
+
+    /// Decide after every failed connection attempt and every dropped connection whether to
+    /// reconnect, see [`RetryDecision::default_for`] for the default.
+    ///
+    /// A connection the server closed is described by a
+    /// [`Closed`](WebsocketError::Closed) error with the close code and reason, also when it
+    /// closed cleanly. When this stops, reads and writes fail with the error, reads return EOF
+    /// for clean closes.
+    ///
+    /// ```rust,no_run
+    /// # use std::time::Duration;
+    /// # use websocket_async_io::{
+    /// #     CloseCode, ReconnectingWebsocketIO, RetryDecision, WebsocketError, WebsocketIO,
+    /// # };
+    /// let builder = WebsocketIO::builder("localhost:8000");
+    /// let ws = ReconnectingWebsocketIO::new(builder).retry_with(|error| {
+    ///     match WebsocketError::from_io(error) {
+    ///         // the server closes with 4401 once the session expired, a new one won't help
+    ///         Some(WebsocketError::Closed {
+    ///             code: CloseCode::Application(4401),
+    ///             ..
+    ///         }) => RetryDecision::Stop,
+    ///         // the server is overloaded, give it some time
+    ///         Some(WebsocketError::Closed {
+    ///             code: CloseCode::TryAgainLater,
+    ///             ..
+    ///         }) => RetryDecision::RetryAfter(Duration::from_secs(30)),
+    ///         _ => RetryDecision::default_for(error),
+    ///     }
+    /// });
+    /// ```
+    pub fn retry_with(
+        mut self,
+        decide: impl Fn(&std::io::Error) -> RetryDecision + 'static,
+    ) -> Self {
+        self.retry = Box::new(decide);
+        self
+    }
+
+    /// How often the connection was reestablished after dropping.
+    pub fn reconnects(&self) -> u64 {
+        self.connections.saturating_sub(1)
+    }
+
+    /// Whether the current connection is open, `false` while reconnecting and after an idle
+    /// close.
+    pub fn is_connected(&self) -> bool {
+        matches!(self.state, State::Connected(..))
+    }
+
+    /// Changes the state, waking every task as the one not driving the change may be waiting on
+    /// the old connection.
+    fn set_state(&mut self, state: State) {
+        self.state = state;
+        self.waiters.wake_all();
+    }
+
+    fn schedule_reconnect(&mut self, delay: Option<Duration>) {
+        let delay = delay.unwrap_or_else(|| {
+            let backoff = self.backoff;
+            self.backoff = (self.backoff * 2).min(self.max_backoff);
+            backoff
+        });
+        self.set_state(State::Waiting(timer::sleep(delay)));
+    }
+
+    /// Asks the retry hook what to do about `error`, scheduling the reconnect if it retries.
+    /// Returns `false` once it stopped.
+    fn retry(&mut self, error: &std::io::Error) -> bool {
+        match (self.retry)(error) {
+            RetryDecision::Retry => self.schedule_reconnect(None),
+            RetryDecision::RetryAfter(delay) => self.schedule_reconnect(Some(delay)),
+            RetryDecision::Stop => {
+                self.set_state(State::Closed);
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Describes how the current connection closed, with the close code and reason once the
+    /// close event fired and `fallback` before.
+    fn closed_error(&self, fallback: std::io::Error) -> std::io::Error {
+        let info = match &self.state {
+            State::Connected(connection) => connection.1.close_signal.info(),
+            _ => None,
+        };
+        match info {
+            Some(info) => WebsocketError::Closed {
+                code: info.code,
+                reason: info.reason,
+            }
+            .into(),
+            None => fallback,
+        }
+    }
+
+    /// Whether the current connection was closed by the idle timeout.
+    fn closed_idle(&self) -> bool {
+        match &self.state {
+            State::Connected(connection) => connection.1.close_signal.was_idle(),
+            _ => false,
+        }
+    }
+
+    /// Drives reconnecting until there is an open connection. After an idle close only writes
+    /// reconnect, reads wait for them.
+    fn poll_connected(
+        &mut self,
+        cx: &mut Context<'_>,
+        write: bool,
+    ) -> Poll<std::io::Result<(&mut WebsocketReader, &mut WebsocketWriter)>> {
+        self.waiters.register(cx.waker());
+        let waker = self.waker.clone();
+        let mut shared = Context::from_waker(&waker);
+        loop {
+            match &mut self.state {
+                State::Connected(..) => break,
+                State::Closed => return Poll::Ready(Err(WebsocketError::WriteClosed.into())),
+                State::Idle if !write => return Poll::Pending,
+                State::Idle => {
+                    let connecting = Box::pin(self.builder.clone().connect());
+                    self.set_state(State::Connecting(connecting));
+                }
+                State::Waiting(sleep) => {
+                    ready!(Pin::new(sleep).poll(&mut shared));
+                    let connecting = Box::pin(self.builder.clone().connect());
+                    self.set_state(State::Connecting(connecting));
+                }
+                State::Connecting(connecting) => {
+                    match ready!(connecting.as_mut().poll(&mut shared)) {
+                        Ok(ws) => {
+                            let (reader, writer) = ws.split();
+                            self.connections += 1;
+                            self.backoff = self.initial_backoff;
+                            self.set_state(State::Connected(Box::new((reader, writer))));
+                        }
+                        Err(e) => {
+                            if !self.retry(&e) {
+                                return Poll::Ready(Err(e));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        match &mut self.state {
+            State::Connected(connection) => {
+                let (reader, writer) = &mut **connection;
+                Poll::Ready(Ok((reader, writer)))
+            }
+            _ => unreachable!("the loop only ends when connected"),
+        }
+    }
+}
+
+/// What [`ReconnectingWebsocketIO`] does after a connection attempt failed or the connection
+/// dropped, decided by [`retry_with`](ReconnectingWebsocketIO::retry_with).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RetryDecision {
+    /// Reconnect after the exponential backoff.
+    Retry,
+    /// Reconnect after the given delay instead of the backoff, e.g. one the server asked for.
+    RetryAfter(Duration),
+    /// Stop reconnecting and report the error.
+    Stop,
+}
+
+impl RetryDecision {
+    /// The default decision: stop on errors another attempt won't fix, like an invalid
+    /// address, a connection the page isn't allowed to make and browsers without websockets,
+    /// and retry everything else, including every dropped connection.
+    pub fn default_for(error: &std::io::Error) -> RetryDecision {
+        use std::io::ErrorKind;
+
+        match error.kind() {
+            ErrorKind::InvalidInput | ErrorKind::PermissionDenied | ErrorKind::Unsupported => {
+                RetryDecision::Stop
+            }
+            _ => RetryDecision::Retry,
+        }
+    }
+}
+
+/// Whether an error of an open connection means it dropped.
+fn is_disconnect(error: &std::io::Error) -> bool {
+    matches!(
+        WebsocketError::from_io(error),
+        Some(
+            WebsocketError::Closed { .. }
+                | WebsocketError::Socket(_)
+                | WebsocketError::IdleTimeout(_)
+                | WebsocketError::WriteClosed
+                | WebsocketError::Send { .. }
+        )
+    )
+}
+
+impl AsyncRead for ReconnectingWebsocketIO {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        if let State::Closed = this.state {
+            return Poll::Ready(Ok(0));
+        }
+        loop {
+            let (reader, _) = ready!(this.poll_connected(cx, false))?;
+            match ready!(Pin::new(reader).poll_read(cx, buf)) {
+                // EOF of the current connection, as the server closed it cleanly
+                Ok(0) if !buf.is_empty() => {
+                    let error = this.closed_error(
+                        WebsocketError::Closed {
+                            code: CloseCode::NoStatus,
+                            reason: String::new(),
+                        }
+                        .into(),
+                    );
+                    if !this.retry(&error) {
+                        return Poll::Ready(Ok(0));
+                    }
+                }
+                // waits for the next write to reconnect
+                Err(e) if is_disconnect(&e) && this.closed_idle() => this.set_state(State::Idle),
+                // the error carries the close code already
+                Err(e) if is_disconnect(&e) => {
+                    if !this.retry(&e) {
+                        return Poll::Ready(Err(e));
+                    }
+                }
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+}
+
+impl AsyncWrite for ReconnectingWebsocketIO {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            let (_, writer) = ready!(this.poll_connected(cx, true))?;
+            match ready!(Pin::new(writer).poll_write(cx, buf)) {
+                // reconnects right away, the connection didn't drop
+                Err(e) if is_disconnect(&e) && this.closed_idle() => this.set_state(State::Idle),
+                // only says the connection is gone, the close event tells why
+                Err(e) if is_disconnect(&e) => {
+                    let error = this.closed_error(e);
+                    if !this.retry(&error) {
+                        return Poll::Ready(Err(error));
+                    }
+                }
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match &mut self.get_mut().state {
+            State::Connected(connection) => Pin::new(&mut connection.1).poll_flush(cx),
+            // nothing was written that could be flushed
+            _ => Poll::Ready(Ok(())),
+        }
+    }
+
+    /// Closes the current connection and stops reconnecting.
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        if let State::Connected(connection) = &mut this.state {
+            ready!(Pin::new(&mut connection.1).poll_close(cx))?;
+        }
+        this.set_state(State::Closed);
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Tasks waiting on the connection, all woken when it changes instead of only the last poller.
+#[derive(Default)]
+struct Waiters(Mutex<Vec<Waker>>);
+
+impl Waiters {
+    fn register(&self, waker: &Waker) {
+        let mut wakers = self.0.lock().unwrap();
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+
+    fn wake_all(&self) {
+        let wakers = std::mem::take(&mut *self.0.lock().unwrap());
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+impl Wake for Waiters {
+    fn wake(self: Arc<Self>) {
+        self.wake_all();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_all();
+    }
+}
@@ -0,0 +1,64 @@
+use std::pin::Pin;
+use std::task::{ready, Poll};
+
+use futures_io::AsyncBufRead;
+
+use crate::WebsocketReader;
+
+/// Gives access to the first bytes of a [`WebsocketReader`] without losing them, created by
+/// [`WebsocketReader::into_sniffer`].
+pub struct Sniffer {
+    reader: WebsocketReader,
+    len: usize,
+    sniffed: Vec<u8>,
+}
+
+impl Sniffer {
+    pub(crate) fn new(reader: WebsocketReader, len: usize) -> Sniffer {
+        Sniffer {
+            reader,
+            len,
+            sniffed: Vec::with_capacity(len),
+        }
+    }
+
+    /// Waits until the first `n` bytes were received and returns them.
+    ///
+    /// Returns fewer bytes if the connection reached EOF before.
+    pub async fn peek(&mut self) -> std::io::Result<&[u8]> {
+        while self.sniffed.len() < self.len {
+            let missing = self.len - self.sniffed.len();
+            let read = std::future::poll_fn(|cx| {
+                let mut reader = Pin::new(&mut self.reader);
+                let buf = ready!(reader.as_mut().poll_fill_buf(cx))?;
+
+                let amount = missing.min(buf.len());
+                self.sniffed.extend_from_slice(&buf[..amount]);
+                reader.consume(amount);
+                Poll::Ready(Ok::<_, std::io::Error>(amount))
+            })
+            .await?;
+
+            if read == 0 {
+                break;
+            }
+        }
+
+        Ok(&self.sniffed)
+    }
+
+    /// Returns the reader, which yields the sniffed bytes again before any other data.
+    ///
+    /// If the sniffed bytes spanned several messages, they are replayed as a single chunk.
+    pub fn into_reader(self) -> WebsocketReader {
+        let Sniffer {
+            mut reader,
+            sniffed,
+            ..
+        } = self;
+
+        reader.remaining.insert_from_slice(0, &sniffed);
+        reader.buffered.set(reader.buffered.get() + sniffed.len());
+        reader
+    }
+}
@@ -0,0 +1,151 @@
+use std::task::{Context, Poll};
+
+#[cfg(feature = "testing")]
+use std::cell::{Cell, RefCell};
+#[cfg(feature = "testing")]
+use std::rc::Rc;
+#[cfg(feature = "testing")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "testing")]
+use std::sync::Arc;
+#[cfg(feature = "testing")]
+use std::task::{Wake, Waker};
+
+/// Poll statistics of a [`WebsocketReader`](crate::WebsocketReader) or
+/// [`WebsocketWriter`](crate::WebsocketWriter), for asserting on the behavior of adapters
+/// around them in tests.
+#[cfg(feature = "testing")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PollStats {
+    /// How often any of the `poll_*` methods was called.
+    pub polls: u64,
+    /// How many of those calls returned `Poll::Pending`.
+    pub pending: u64,
+    /// How often a waker passed to one of the `poll_*` methods was woken.
+    pub wakes: u64,
+}
+
+/// Counts polls and wakes with the `testing` feature, and does nothing otherwise.
+#[derive(Clone, Default)]
+pub(crate) struct PollCounter {
+    #[cfg(feature = "testing")]
+    counts: Rc<Counts>,
+}
+
+#[cfg(feature = "testing")]
+#[derive(Default)]
+struct Counts {
+    polls: Cell<u64>,
+    pending: Cell<u64>,
+    wakes: Arc<AtomicU64>,
+    /// the last waker passed in and its counting wrapper, reused so that `will_wake` stays
+    /// reliable for whatever stores the wrapper
+    cached: RefCell<Option<(Waker, Waker)>>,
+}
+
+impl PollCounter {
+    #[cfg(not(feature = "testing"))]
+    #[inline]
+    pub(crate) fn track<T>(
+        &self,
+        cx: &mut Context<'_>,
+        poll: impl FnOnce(&mut Context<'_>) -> Poll<T>,
+    ) -> Poll<T> {
+        poll(cx)
+    }
+
+    #[cfg(feature = "testing")]
+    pub(crate) fn track<T>(
+        &self,
+        cx: &mut Context<'_>,
+        poll: impl FnOnce(&mut Context<'_>) -> Poll<T>,
+    ) -> Poll<T> {
+        let counts = &self.counts;
+        counts.polls.set(counts.polls.get() + 1);
+
+        let waker = {
+            let mut cached = counts.cached.borrow_mut();
+            match &*cached {
+                Some((inner, waker)) if inner.will_wake(cx.waker()) => waker.clone(),
+                _ => {
+                    let waker = Waker::from(Arc::new(CountingWaker {
+                        inner: cx.waker().clone(),
+                        wakes: Arc::clone(&counts.wakes),
+                    }));
+                    *cached = Some((cx.waker().clone(), waker.clone()));
+                    waker
+                }
+            }
+        };
+        let result = poll(&mut Context::from_waker(&waker));
+
+        if result.is_pending() {
+            counts.pending.set(counts.pending.get() + 1);
+        }
+        result
+    }
+
+    #[cfg(feature = "testing")]
+    pub(crate) fn stats(&self) -> PollStats {
+        PollStats {
+            polls: self.counts.polls.get(),
+            pending: self.counts.pending.get(),
+            wakes: self.counts.wakes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(feature = "testing")]
+struct CountingWaker {
+    inner: Waker,
+    wakes: Arc<AtomicU64>,
+}
+
+#[cfg(feature = "testing")]
+impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wakes.fetch_add(1, Ordering::Relaxed);
+        self.inner.wake_by_ref();
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::close::CloseSignal;
+    use crate::{CloseCode, CloseInfo};
+
+    struct Task;
+
+    impl Wake for Task {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    #[test]
+    fn reuses_waker_across_polls() {
+        let counter = PollCounter::default();
+        let signal = CloseSignal::default();
+        let task = Waker::from(Arc::new(Task));
+        let mut cx = Context::from_waker(&task);
+
+        for _ in 0..3 {
+            assert!(counter.track(&mut cx, |cx| signal.poll(cx)).is_pending());
+        }
+        signal.set(CloseInfo {
+            code: CloseCode::Normal,
+            reason: String::new(),
+            was_clean: true,
+        });
+        assert!(counter.track(&mut cx, |cx| signal.poll(cx)).is_ready());
+
+        let stats = counter.stats();
+        assert_eq!(stats.polls, 4);
+        assert_eq!(stats.pending, 3);
+        // the signal kept a single waker instead of one per poll
+        assert_eq!(stats.wakes, 1);
+    }
+}
@@ -0,0 +1,58 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_channel::oneshot;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+#[wasm_bindgen]
+extern "C" {
+    // imported from the global scope so this works in windows and workers alike. The handle
+    // is a number there, but an object in node, where the tests run.
+    #[wasm_bindgen(js_name = setTimeout)]
+    fn set_timeout(handler: &js_sys::Function, timeout: i32) -> JsValue;
+    #[wasm_bindgen(js_name = clearTimeout)]
+    fn clear_timeout(handle: &JsValue);
+}
+
+/// Future resolving after a `setTimeout` fired. Dropping it clears the timeout.
+pub(crate) struct Sleep {
+    rx: oneshot::Receiver<()>,
+    handle: JsValue,
+    _callback: Closure<dyn FnMut()>,
+}
+
+pub(crate) fn sleep(duration: Duration) -> Sleep {
+    let (tx, rx) = oneshot::channel();
+    let mut tx = Some(tx);
+    let callback = Closure::wrap(Box::new(move || {
+        if let Some(tx) = tx.take() {
+            let _ = tx.send(());
+        }
+    }) as Box<dyn FnMut()>);
+
+    let timeout = duration.as_millis().min(i32::MAX as u128) as i32;
+    let handle = set_timeout(callback.as_ref().unchecked_ref(), timeout);
+
+    Sleep {
+        rx,
+        handle,
+        _callback: callback,
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        Pin::new(&mut self.rx).poll(cx).map(|_| ())
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        clear_timeout(&self.handle);
+    }
+}
@@ -0,0 +1,58 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// How long the phases of establishing a connection took, from
+/// [`WebsocketIO::connect_timing`](crate::WebsocketIO::connect_timing).
+///
+/// Browsers don't expose DNS, TCP and TLS timings of websockets, so the handshake covers all of
+/// them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnectTiming {
+    /// From creating the `WebSocket` to its `open` event.
+    pub handshake: Duration,
+    /// From the `open` event to the first received message, `None` until one arrived.
+    pub first_message: Option<Duration>,
+}
+
+/// Timestamps of a connection's phases in milliseconds since the epoch.
+#[derive(Clone)]
+pub(crate) struct Timestamps {
+    started: f64,
+    opened: Rc<Cell<Option<f64>>>,
+    first_message: Rc<Cell<Option<f64>>>,
+}
+
+impl Timestamps {
+    pub(crate) fn start() -> Timestamps {
+        Timestamps {
+            started: js_sys::Date::now(),
+            opened: Rc::default(),
+            first_message: Rc::default(),
+        }
+    }
+
+    pub(crate) fn opened(&self) {
+        self.opened.set(Some(js_sys::Date::now()));
+    }
+
+    pub(crate) fn message(&self, received_at: f64) {
+        if self.first_message.get().is_none() {
+            self.first_message.set(Some(received_at));
+        }
+    }
+
+    /// Time since the connection was created.
+    pub(crate) fn elapsed(&self) -> Duration {
+        Duration::from_millis((js_sys::Date::now() - self.started).max(0.0) as u64)
+    }
+
+    pub(crate) fn timing(&self) -> ConnectTiming {
+        let between = |from: f64, to: f64| Duration::from_millis((to - from).max(0.0) as u64);
+        let opened = self.opened.get().unwrap_or(self.started);
+        ConnectTiming {
+            handshake: between(self.started, opened),
+            first_message: self.first_message.get().map(|at| between(opened, at)),
+        }
+    }
+}
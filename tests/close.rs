@@ -0,0 +1,113 @@
+//! Closing against the echo server, which answers close frames with the code and reason it
+//! received, and closes with the code it's asked to. Start it before running the ignored tests:
+//!
+//! ```sh
+//! cargo run -p echo-server --target x86_64-unknown-linux-gnu
+//! CARGO_TARGET_WASM32_UNKNOWN_UNKNOWN_RUNNER=wasm-bindgen-test-runner \
+//!     cargo test --test close -- --include-ignored
+//! ```
+#![cfg(target_arch = "wasm32")]
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use futures_util::io::{AsyncReadExt, AsyncWriteExt};
+use wasm_bindgen_test::wasm_bindgen_test;
+use websocket_async_io::{
+    CloseCode, ReconnectingWebsocketIO, RetryDecision, WebsocketError, WebsocketIO,
+};
+
+const ECHO_SERVER: &str = "127.0.0.1:8000";
+
+#[wasm_bindgen_test]
+#[ignore = "needs the echo server"]
+async fn server_receives_close_code_and_reason() {
+    let mut ws = WebsocketIO::new(ECHO_SERVER).await.unwrap();
+    let closed = ws.closed();
+    ws.close_with(4000, "done here").unwrap();
+
+    let info = closed.await;
+    assert_eq!(info.code, CloseCode::Application(4000));
+    assert_eq!(info.reason, "done here");
+    assert!(info.was_clean);
+}
+
+#[wasm_bindgen_test]
+#[ignore = "needs the echo server"]
+async fn server_receives_truncated_reason() {
+    let (mut reader, mut writer) = WebsocketIO::new(ECHO_SERVER).await.unwrap().split();
+    let closed = writer.closed();
+    let reason = "ä".repeat(100);
+    writer.close_with(3001, &reason).unwrap();
+
+    // the close was started by this side, so the reader ends without an error
+    let mut read = Vec::new();
+    reader.read_to_end(&mut read).await.unwrap();
+
+    let info = closed.await;
+    assert_eq!(info.code, CloseCode::Library(3001));
+    assert_eq!(info.reason, "ä".repeat(61));
+}
+
+fn close_code(error: &std::io::Error) -> Option<(CloseCode, String)> {
+    match WebsocketError::from_io(error) {
+        Some(WebsocketError::Closed { code, reason }) => Some((*code, reason.clone())),
+        _ => None,
+    }
+}
+
+#[wasm_bindgen_test]
+#[ignore = "needs the echo server"]
+async fn reader_receives_server_close_code() {
+    let (mut reader, mut writer) = WebsocketIO::new(ECHO_SERVER).await.unwrap().split();
+    writer.write_all(b"close 4001 auth expired").await.unwrap();
+
+    let error = reader.read(&mut [0; 16]).await.unwrap_err();
+    let expected = (CloseCode::Application(4001), "auth expired".to_string());
+    assert_eq!(close_code(&error), Some(expected));
+}
+
+#[wasm_bindgen_test]
+#[ignore = "needs the echo server"]
+async fn retry_decision_receives_server_close_code() {
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let seen_c = Rc::clone(&seen);
+    let builder = WebsocketIO::builder(ECHO_SERVER);
+    let mut ws = ReconnectingWebsocketIO::new(builder).retry_with(move |error| {
+        let code = close_code(error).map(|(code, _)| code);
+        seen_c.borrow_mut().push(code);
+        match code {
+            Some(CloseCode::Application(4401)) => RetryDecision::Stop,
+            _ => RetryDecision::default_for(error),
+        }
+    });
+    ws.write_all(b"close 4401 auth expired").await.unwrap();
+
+    let error = ws.read(&mut [0; 16]).await.unwrap_err();
+    let expected = (CloseCode::Application(4401), "auth expired".to_string());
+    assert_eq!(close_code(&error), Some(expected));
+    assert_eq!(*seen.borrow(), [Some(CloseCode::Application(4401))]);
+    assert_eq!(ws.reconnects(), 0);
+    assert!(!ws.is_connected());
+}
+
+#[wasm_bindgen_test]
+#[ignore = "needs the echo server"]
+async fn copy_closes_both_connections_on_error() {
+    // `a` is dropped by the server without a closing handshake, which fails the relay
+    let a = WebsocketIO::new(&format!("{}/drop", ECHO_SERVER))
+        .await
+        .unwrap();
+    let b = WebsocketIO::new(ECHO_SERVER).await.unwrap();
+    let b_closed = b.closed();
+
+    let error = websocket_async_io::copy_bidirectional(a, b)
+        .await
+        .unwrap_err();
+    assert!(WebsocketError::from_io(&error).is_some());
+
+    // closed like a writer closes, without a code
+    let info = b_closed.await;
+    assert_eq!(info.code, CloseCode::NoStatus);
+    assert!(info.was_clean);
+}
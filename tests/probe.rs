@@ -0,0 +1,59 @@
+//! Probing the echo server, which sends a greeting when connecting to `/greeting`. Start it
+//! before running the ignored tests:
+//!
+//! ```sh
+//! cargo run -p echo-server --target x86_64-unknown-linux-gnu
+//! CARGO_TARGET_WASM32_UNKNOWN_UNKNOWN_RUNNER=wasm-bindgen-test-runner \
+//!     cargo test --test probe -- --include-ignored
+//! ```
+#![cfg(target_arch = "wasm32")]
+
+use std::time::Duration;
+
+use wasm_bindgen_test::wasm_bindgen_test;
+use websocket_async_io::{ProbeResult, WebsocketError, WebsocketIO};
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+fn hello(message: &[u8]) -> bool {
+    message == b"hello"
+}
+
+#[wasm_bindgen_test]
+async fn probe_returns_invalid_address() {
+    let error = WebsocketIO::probe("ws://localhost:8000", hello, TIMEOUT)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        WebsocketError::from_io(&error),
+        Some(WebsocketError::InvalidAddress { .. })
+    ));
+}
+
+#[wasm_bindgen_test]
+#[ignore = "needs the echo server"]
+async fn probe_accepts_expected_greeting() {
+    let result = WebsocketIO::probe("127.0.0.1:8000/greeting", hello, TIMEOUT).await;
+    assert_eq!(result.unwrap(), ProbeResult::Ok);
+}
+
+#[wasm_bindgen_test]
+#[ignore = "needs the echo server"]
+async fn probe_rejects_other_greeting() {
+    let result = WebsocketIO::probe("127.0.0.1:8000/greeting", |m| m == b"hi", TIMEOUT).await;
+    assert_eq!(result.unwrap(), ProbeResult::WrongProtocol);
+}
+
+#[wasm_bindgen_test]
+#[ignore = "needs the echo server"]
+async fn probe_rejects_closed_connection() {
+    let result = WebsocketIO::probe("127.0.0.1:8000/drop", hello, TIMEOUT).await;
+    assert_eq!(result.unwrap(), ProbeResult::WrongProtocol);
+}
+
+#[wasm_bindgen_test]
+#[ignore = "needs the echo server"]
+async fn probe_times_out_without_greeting() {
+    let result = WebsocketIO::probe("127.0.0.1:8000", hello, TIMEOUT).await;
+    assert_eq!(result.unwrap(), ProbeResult::Timeout);
+}
@@ -0,0 +1,146 @@
+//! Reconnecting against the echo server, which drops the connection when asked to. Start it
+//! before running the ignored tests, see `tests/close.rs`.
+#![cfg(target_arch = "wasm32")]
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures_util::future::{select, Either};
+use futures_util::io::{AsyncReadExt, AsyncWriteExt};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use wasm_bindgen_test::wasm_bindgen_test;
+use websocket_async_io::{
+    CloseCode, ReconnectingWebsocketIO, RetryDecision, WebsocketError, WebsocketIO,
+};
+
+const ECHO_SERVER: &str = "127.0.0.1:8000";
+
+fn reconnecting(addr: &str) -> ReconnectingWebsocketIO {
+    ReconnectingWebsocketIO::new(WebsocketIO::builder(addr))
+        .backoff(Duration::from_millis(10), Duration::from_millis(100))
+}
+
+async fn sleep(ms: i32) {
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        let set_timeout = js_sys::Reflect::get(&js_sys::global(), &"setTimeout".into()).unwrap();
+        let set_timeout: js_sys::Function = set_timeout.unchecked_into();
+        set_timeout
+            .call2(&JsValue::NULL, &resolve, &ms.into())
+            .unwrap();
+    });
+    JsFuture::from(promise).await.unwrap();
+}
+
+#[wasm_bindgen_test]
+async fn stops_on_invalid_address() {
+    let mut ws = reconnecting("ws://localhost:8000");
+
+    let error = ws.read(&mut [0; 16]).await.unwrap_err();
+    assert!(matches!(
+        WebsocketError::from_io(&error),
+        Some(WebsocketError::InvalidAddress { .. })
+    ));
+    assert_eq!(ws.read(&mut [0; 16]).await.unwrap(), 0);
+    assert!(ws.write_all(b"hello").await.is_err());
+}
+
+#[wasm_bindgen_test]
+#[ignore = "needs the echo server"]
+async fn reconnects_after_server_dropped_connection() {
+    let ws = ReconnectingWebsocketIO::new(WebsocketIO::builder(ECHO_SERVER))
+        .backoff(Duration::from_millis(300), Duration::from_secs(1));
+    let (mut reader, mut writer) = ws.split();
+    let mut buf = [0; 16];
+
+    writer.write_all(b"hello").await.unwrap();
+    let n = reader.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hello");
+
+    // the reading task notices the drop, another task writes while waiting for the reconnect,
+    // both have to be woken once it's back
+    writer.write_all(b"drop").await.unwrap();
+    let (writer_tx, writer_rx) = futures_channel::oneshot::channel();
+    wasm_bindgen_futures::spawn_local(async move {
+        sleep(100).await;
+        writer.write_all(b"again").await.unwrap();
+        let _ = writer_tx.send(writer);
+    });
+    let n = reader.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"again");
+    let writer = writer_rx.await.unwrap();
+
+    let ws = reader.reunite(writer).unwrap();
+    assert_eq!(ws.reconnects(), 1);
+    assert!(ws.is_connected());
+}
+
+#[wasm_bindgen_test]
+#[ignore = "needs the echo server"]
+async fn retries_until_decision_stops() {
+    let attempts = Rc::new(Cell::new(0));
+    let attempts_c = Rc::clone(&attempts);
+    // nothing listens on the port, so every attempt is refused or times out
+    let builder = WebsocketIO::builder("127.0.0.1:1").connect_timeout(Duration::from_millis(200));
+    let mut ws = ReconnectingWebsocketIO::new(builder).retry_with(move |_| {
+        attempts_c.set(attempts_c.get() + 1);
+        match attempts_c.get() {
+            1 => RetryDecision::RetryAfter(Duration::from_millis(1)),
+            2 => RetryDecision::Retry,
+            _ => RetryDecision::Stop,
+        }
+    });
+
+    let error = ws.read(&mut [0; 16]).await.unwrap_err();
+    assert!(matches!(
+        WebsocketError::from_io(&error),
+        Some(WebsocketError::HandshakeRejected(_) | WebsocketError::ConnectTimeout(_))
+    ));
+    assert_eq!(attempts.get(), 3);
+    assert_eq!(ws.reconnects(), 0);
+}
+
+#[wasm_bindgen_test]
+#[ignore = "needs the echo server"]
+async fn stops_after_close() {
+    let mut ws = reconnecting(ECHO_SERVER);
+    ws.write_all(b"hello").await.unwrap();
+    ws.close().await.unwrap();
+
+    assert!(!ws.is_connected());
+    assert_eq!(ws.read(&mut [0; 16]).await.unwrap(), 0);
+    let error = ws.write_all(b"hello").await.unwrap_err();
+    assert!(matches!(
+        WebsocketError::from_io(&error),
+        Some(WebsocketError::WriteClosed)
+    ));
+    assert_eq!(ws.reconnects(), 0);
+}
+
+#[wasm_bindgen_test]
+#[ignore = "needs the echo server"]
+async fn reconnects_on_write_after_idle_close() {
+    let builder = WebsocketIO::builder(ECHO_SERVER)
+        .idle_timeout(Duration::from_millis(100), CloseCode::Normal);
+    let mut ws = ReconnectingWebsocketIO::new(builder);
+    let mut buf = [0; 16];
+
+    ws.write_all(b"hello").await.unwrap();
+    let n = ws.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hello");
+
+    // the read sees the idle close and waits instead of reconnecting
+    let read = ws.read(&mut buf);
+    match select(std::pin::pin!(read), std::pin::pin!(sleep(300))).await {
+        Either::Left(..) => panic!("read returned after the idle close"),
+        Either::Right(..) => {}
+    }
+    assert!(!ws.is_connected());
+    assert_eq!(ws.reconnects(), 0);
+
+    ws.write_all(b"again").await.unwrap();
+    let n = ws.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"again");
+    assert_eq!(ws.reconnects(), 1);
+}